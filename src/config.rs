@@ -1,12 +1,160 @@
 use std::path::PathBuf;
 
 use anyhow::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 #[derive(Debug, Deserialize, PartialEq)]
+#[serde(remote = "Self")]
 pub enum BitShape {
-    V,
+    /// `angle` is the included angle of the tip in degrees, only needed where the cut depends on it.
+    ///
+    /// A plain `V` is a V bit without an angle.
+    V { angle: Option<f64> },
     Square { radius: f64 },
+    /// Single profile thread mill, `radius` is measured at the tip of the profile
+    ThreadMill { radius: f64 },
+}
+
+impl<'de> Deserialize<'de> for BitShape {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        enum Plain {
+            V,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Shape {
+            Plain(Plain),
+            Full(#[serde(with = "BitShape")] BitShape),
+        }
+
+        Ok(match Shape::deserialize(deserializer)? {
+            Shape::Plain(Plain::V) => BitShape::V { angle: None },
+            Shape::Full(shape) => shape,
+        })
+    }
+}
+
+/// Which side of the drawn contour the bit cuts on
+#[derive(Debug, Default, Deserialize)]
+pub enum CutSide {
+    /// Cut a part out, the bit stays outside of it
+    #[default]
+    Outside,
+    /// Cut a hole or a window, the bit stays inside of it
+    Inside,
+    /// The bit follows the contour, e.g. for slots
+    On,
+}
+
+/// Bridges left uncut along the contours to hold the parts in place
+#[derive(Debug, Deserialize)]
+pub struct TabsConfig {
+    /// Number of tabs on each contour, either this or `spacing` should be set
+    pub count: Option<usize>,
+    /// Distance between the tabs along each contour
+    pub spacing: Option<f64>,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub enum ThreadKind {
+    /// Thread inside a hole, the circle is its major diameter
+    #[default]
+    Internal,
+    /// Thread around a boss, the circle is its major diameter
+    External,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub enum ReliefStyle {
+    /// Overcut diagonally into the corner
+    #[default]
+    Dogbone,
+    /// Overcut along the wall leading into the corner
+    TBone,
+}
+
+/// Overcuts into the inside corners of parts, which a round bit can't cut sharp
+#[derive(Debug, Deserialize)]
+pub struct CornerRelief {
+    #[serde(default)]
+    pub style: ReliefStyle,
+    /// Corners with an angle up to this many degrees are relieved
+    pub max_angle: f64,
+}
+
+/// Last pass along the contours at full depth, after roughing passes that leave some stock
+#[derive(Debug, Deserialize)]
+pub struct FinishConfig {
+    /// Stock left on the walls by the roughing passes
+    pub allowance: f64,
+    pub feed: f64,
+    /// Go the opposite way to the roughing passes
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+/// Which way the bit goes along the contours, for a clockwise spindle
+#[derive(Debug, Deserialize)]
+pub enum MillingDirection {
+    /// The bit moves with the material on its right
+    Climb,
+    /// The bit moves with the material on its left
+    Conventional,
+}
+
+/// Moves onto and off the contour, on the waste side of the cut
+#[derive(Debug, Deserialize)]
+pub enum Lead {
+    /// Quarter circle tangent to the contour
+    Arc {
+        radius: f64,
+    },
+    /// Straight line perpendicular to the contour
+    Line {
+        length: f64,
+    },
+}
+
+/// How the bit gets down to the depth of a pass at the start of a contour
+#[derive(Debug, Default, Deserialize)]
+pub enum EntryStrategy {
+    /// Straight down
+    #[default]
+    Plunge,
+    /// Back and forth along the start of the contour, descending at most at `angle` degrees
+    Ramp {
+        angle: f64,
+    },
+    /// Down a helix ending tangent to the contour at its start, descending at `angle` degrees
+    Helix {
+        radius: f64,
+        angle: f64,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub enum PocketStrategy {
+    /// Offsets of the contour, from the middle outwards
+    #[default]
+    Concentric,
+    /// Parallel lines at `angle` degrees to the X axis
+    Raster {
+        angle: f64,
+        /// Go around the boundary after the lines to clean up the scallops
+        #[serde(default)]
+        finish_contour: bool,
+    },
 }
 
 #[derive(Debug, Deserialize)]
@@ -14,37 +162,137 @@ pub enum JobKind {
     EngraveContours {
         depth: f64,
         offset: f64,
+        direction: Option<MillingDirection>,
     },
     CutContours {
         depth: f64,
         depth_per_pass: f64,
+        #[serde(default)]
+        side: CutSide,
+        tabs: Option<TabsConfig>,
+        lead: Option<Lead>,
+        direction: Option<MillingDirection>,
+        finish: Option<FinishConfig>,
+        relief: Option<CornerRelief>,
+    },
+    PocketContours {
+        depth: f64,
+        depth_per_pass: f64,
+        stepover: f64,
+        #[serde(default)]
+        strategy: PocketStrategy,
+        direction: Option<MillingDirection>,
+    },
+    /// Bevel the top edges of the contours that `CutContours` cuts along, with a V bit
+    ChamferContours {
+        /// Horizontal width of the bevel
+        width: f64,
+        /// Distance to keep the tip of the bit away from the edge, into the waste
+        #[serde(default)]
+        clearance: f64,
+        #[serde(default)]
+        side: CutSide,
+        direction: Option<MillingDirection>,
+    },
+    /// Carve the polygons with a V bit, going deeper where they are wider
+    VCarveContours {
+        /// Depth at which the bottom of the carve becomes flat
+        max_depth: Option<f64>,
+        /// Clear the flat bottom
+        #[serde(default)]
+        clearing: bool,
+        /// Depth of each of the passes clearing the flat bottom
+        depth_per_pass: Option<f64>,
+        /// Height of the ridges the bit leaves between the passes clearing the flat bottom, the resolution if not set
+        scallop_height: Option<f64>,
     },
     DrillCircles {
         depth: f64,
         radius_min: Option<f64>,
         radius_max: Option<f64>,
+        /// Retract after drilling this much deeper to clear the chips
+        peck_depth: Option<f64>,
+        /// Seconds to wait at the bottom of each hole
+        dwell: Option<f64>,
+        /// Use the G81/G82/G83 drilling cycles instead of separate moves, where the dialect has them
+        #[serde(default)]
+        canned_cycle: bool,
     },
     BoreCircles {
         depth: f64,
         depth_per_turn: f64,
         radius_min: Option<f64>,
         radius_max: Option<f64>,
+        /// Distance between the helices clearing holes wider than the bit diameter, the bit radius if not set
+        stepover: Option<f64>,
     },
+    ThreadMillCircles {
+        /// Length of the thread
+        depth: f64,
+        pitch: f64,
+        /// Radial depth of the thread profile
+        thread_depth: f64,
+        #[serde(default)]
+        thread: ThreadKind,
+        #[serde(default)]
+        hand: Handedness,
+        radius_min: Option<f64>,
+        radius_max: Option<f64>,
+    },
+}
+
+/// Which SVG elements a job uses.
+///
+/// Every non-empty list has to match the element or one of its ancestor groups,
+/// an element matches a list if it matches any of its entries.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SelectConfig {
+    /// Inkscape layer names (`inkscape:label` on groups)
+    pub layers: Vec<String>,
+    pub ids: Vec<String>,
+    pub classes: Vec<String>,
+    /// Stroke or fill colours, e.g. `#ff0000` or `red`
+    pub colors: Vec<String>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct JobConfig {
     pub input: PathBuf,
+    #[serde(default)]
+    pub select: SelectConfig,
     pub kind: JobKind,
     pub bit_shape: BitShape,
     pub feed: f64,
+    /// Feed for moves going down, same as `feed` if not set
+    pub plunge_feed: Option<f64>,
+    #[serde(default)]
+    pub entry: EntryStrategy,
     pub rpm: f64,
 }
 
+/// The G-code dialect of the controller
+#[derive(Debug, Default, Deserialize)]
+pub enum Dialect {
+    #[default]
+    LinuxCnc,
+    Grbl,
+    Mach3,
+    Marlin,
+    Smoothieware,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SharedFabConfig {
     pub resolution: f64,
     pub safe_height: f64,
+    /// Stroke width in millimetres for SVG elements that don't set one
+    pub default_stroke_width: Option<f64>,
+    /// Replace runs of short moves with G2/G3 arcs that deviate at most this much from them,
+    /// curves are only fitted if this is at least the resolution they were flattened with
+    pub arc_tolerance: Option<f64>,
+    #[serde(default)]
+    pub dialect: Dialect,
 }
 
 #[derive(Debug, Deserialize)]
@@ -1,11 +1,11 @@
-use std::iter::once;
+use std::{f64::consts::{FRAC_PI_2, PI, TAU}, iter::once};
 
 use anyhow::{bail, ensure, Result};
-use geo::{BooleanOps, Coord, Intersects, LineString, MultiPolygon, Polygon, Simplify, Vector2DOps};
+use geo::{orient::{Direction, Orient}, BooleanOps, Contains, Coord, Distance, Euclidean, Intersects, Length, Line, LineString, MultiPolygon, Polygon, Simplify, Vector2DOps};
 use geo_offset::Offset;
-use log::debug;
+use log::{debug, warn};
 
-use crate::{config::{JobConfig, SharedFabConfig}, io::svg_input::SvgPrimitives, shape::EPSILON};
+use crate::{config::{CornerRelief, CutSide, EntryStrategy, FinishConfig, Handedness, JobConfig, Lead, MillingDirection, PocketStrategy, ReliefStyle, SharedFabConfig, TabsConfig, ThreadKind}, io::svg_input::SvgPrimitives, shape::{hatch, medial_axis, CoordExt, EllipticalArc, Flatten, EPSILON}};
 
 #[derive(Debug)]
 pub struct Hole {
@@ -22,56 +22,567 @@ impl Hole {
     }
 }
 
+#[derive(Debug)]
+pub struct FabTabs {
+    /// Depth of the top of the tabs
+    pub depth: f64,
+    /// Start and end distances of the tabs along each contour, in the same order as the contours
+    pub spans: Vec<Vec<(f64, f64)>>,
+}
+
 #[derive(Debug)]
 pub struct FabContourData {
     pub contours: Vec<LineString>,
     pub depths: Vec<f64>,
+    pub tabs: Option<FabTabs>,
+    /// Where the bit can go around the contours without cutting into the part, when it's known
+    pub waste: Option<FabWaste>,
+}
+
+/// Merge the polygons that intersect each other
+fn unite(mut polygons: Vec<Polygon>, resolution: f64) -> Vec<Polygon> {
+    let find_next = |polygons: &Vec<Polygon>, current: &MultiPolygon| -> Option<usize> {
+        polygons.iter()
+            .enumerate()
+            .find_map(|(i, p)| if p.intersects(current) { Some(i) } else { None })
+    };
+
+    let mut polygons_united = vec![];
+    while let Some(p_leader) = polygons.pop() {
+        let mut p_leader = MultiPolygon::from(p_leader);
+
+        while let Some(pi) = find_next(&polygons, &p_leader) {
+            let p = polygons.remove(pi);
+            p_leader = p_leader.union(&p);
+        }
+
+        p_leader = p_leader.simplify(&(resolution / 5.0));
+
+        polygons_united.extend(p_leader.into_iter());
+    }
+
+    polygons_united
+}
+
+/// The area enclosed by contours that don't cross each other
+#[derive(Debug)]
+struct Enclosed(Vec<Polygon>);
+
+impl Enclosed {
+    fn new(contours: &[LineString]) -> Self {
+        Self(contours.iter()
+            .filter(|contour| contour.is_closed())
+            .map(|contour| Polygon::new(contour.clone(), vec![]))
+            .collect())
+    }
+
+    /// A point is inside when it is in an odd number of the contours
+    fn contains(&self, p: Coord) -> bool {
+        self.0.iter().filter(|ring| ring.contains(&p)).count() % 2 == 1
+    }
+
+    /// Unit normal of the line pointing to the waste side
+    fn waste_normal(&self, line: &Line, waste_inside: bool, resolution: f64) -> Option<Coord> {
+        let left = Coord { x: -line.dy(), y: line.dx() }.try_normalize()?;
+        let probe = (line.start + line.end) / 2.0 + left * (resolution / 10.0);
+
+        Some(if self.contains(probe) == waste_inside { left } else { -left })
+    }
+}
+
+/// The side of the contours that is waste
+#[derive(Debug)]
+pub struct FabWaste {
+    enclosed: Enclosed,
+    inside: bool,
+    resolution: f64,
+}
+
+impl FabWaste {
+    fn new(contours: &[LineString], inside: bool, resolution: f64) -> Self {
+        Self {
+            enclosed: Enclosed::new(contours),
+            inside,
+            resolution,
+        }
+    }
+
+    /// Unit normal of the line pointing to the waste side
+    pub fn normal(&self, line: &Line) -> Option<Coord> {
+        self.enclosed.waste_normal(line, self.inside, self.resolution)
+    }
+
+    /// Whether the circle is in the waste, apart from the point at `start` which can be on the contours
+    pub fn fits_circle(&self, center: Coord, start: Coord, radius: f64) -> bool {
+        let segments = (TAU * radius / self.resolution).ceil().max(8.0) as usize;
+        let from = start - center;
+
+        (1..segments).all(|i| {
+            let (sin, cos) = (TAU * i as f64 / segments as f64).sin_cos();
+            let p = center + Coord { x: from.x * cos - from.y * sin, y: from.x * sin + from.y * cos };
+            self.enclosed.contains(p) == self.inside
+        })
+    }
+}
+
+/// Orientation of the rings of polygons that makes the bit go in the given direction.
+///
+/// With a clockwise spindle the bit climbs when the waste is on its left.
+fn ring_orientation(direction: &MillingDirection, waste_inside: bool) -> Direction {
+    // Exteriors are counterclockwise by default, so the inside of the polygon is on the left
+    match (direction, waste_inside) {
+        (MillingDirection::Climb, true) | (MillingDirection::Conventional, false) => Direction::Default,
+        (MillingDirection::Climb, false) | (MillingDirection::Conventional, true) => Direction::Reversed,
+    }
+}
+
+/// Depths of the passes needed to reach `depth` taking at most `depth_per_pass` at a time
+fn pass_depths(depth: f64, depth_per_pass: f64) -> Vec<f64> {
+    // The last pass always ends at the depth, so a depth that is a multiple of the depth per pass isn't cut twice
+    let passes = (depth / depth_per_pass - EPSILON).ceil().max(1.0) as usize;
+
+    debug!("Cutting in {passes} passes: depth {depth}, depth_per_pass: {depth_per_pass}");
+
+    (1..passes)
+        .map(|pass| depth_per_pass * pass as f64)
+        .chain(once(depth))
+        .collect()
 }
 
 impl FabContourData {
-    pub fn new(polygons: MultiPolygon, depths: Vec<f64>, offset: f64, resolution: f64) -> Self {
+    pub fn new(polygons: MultiPolygon, depths: Vec<f64>, offset: f64, orientation: Option<Direction>, resolution: f64) -> Self {
         let arc_resolution = geo_offset::ArcResolution::SegmentLength(resolution);
 
         let mut polygons_offset = vec![];
         for polygon in polygons {
-            polygons_offset.extend(polygon.offset_with_arc_resolution(offset, arc_resolution).unwrap());
+            match polygon.offset_with_arc_resolution(offset, arc_resolution) {
+                Ok(p) => polygons_offset.extend(p),
+                // Shrinking can make small polygons disappear
+                Err(_) => warn!("Could not offset a polygon by {offset}, skipping it"),
+            }
         }
-        // let mut contours = MultiPolygon::from(polygons_offset);
 
-        let find_next = |polygons: &Vec<Polygon>, current: &MultiPolygon| -> Option<usize> {
-            polygons.iter()
-                .enumerate()
-                .find_map(|(i, p)| if p.intersects(current) { Some(i) } else { None })
-        };
+        let polygons_united = unite(polygons_offset, resolution);
 
-        let mut polygons_united = vec![];
-        while let Some(p_leader) = polygons_offset.pop() {
-            let mut p_leader = MultiPolygon::from(p_leader);
+        let mut contours = vec![];
 
-            while let Some(pi) = find_next(&polygons_offset, &p_leader) {
-                let p = polygons_offset.remove(pi);
-                p_leader = p_leader.union(&p);
+        for mut polygon in polygons_united {
+            if let Some(orientation) = orientation {
+                polygon = polygon.orient(orientation);
             }
 
-            p_leader = p_leader.simplify(&(resolution / 5.0));
+            contours.extend(polygon.interiors().into_iter().cloned());
+            contours.push(polygon.exterior().clone());
+        }
 
-            polygons_united.extend(p_leader.into_iter());
+        Self {
+            contours,
+            depths,
+            tabs: None,
+            waste: None,
         }
+    }
+
+    /// Keep track of which side of the contours is waste, before leads or anything else are added to them
+    pub fn with_waste(mut self, waste_inside: bool, resolution: f64) -> Self {
+        self.waste = Some(FabWaste::new(&self.contours, waste_inside, resolution));
+        self
+    }
+
+    /// Place tabs evenly along each contour, the bit is lifted over `width` plus its diameter
+    pub fn with_tabs(mut self, tabs: &TabsConfig, depth: f64, bit_radius: f64) -> Self {
+        let span = tabs.width + bit_radius * 2.0;
+
+        let spans = self.contours.iter()
+            .map(|contour| {
+                let length = contour.length::<Euclidean>();
+
+                let mut count = match (tabs.count, tabs.spacing) {
+                    (Some(count), _) => count,
+                    (None, Some(spacing)) => (length / spacing).floor().max(1.0) as usize,
+                    (None, None) => 0,
+                };
+
+                // Leave at least as much cut as there are tabs
+                while count > 0 && count as f64 * span * 2.0 > length {
+                    count -= 1;
+                }
+
+                (0..count)
+                    .map(|i| {
+                        let center = (i as f64 + 0.5) * length / count as f64;
+                        (center - span / 2.0, center + span / 2.0)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        self.tabs = Some(FabTabs {
+            depth: depth - tabs.height,
+            spans,
+        });
+
+        self
+    }
+
+    /// Add lead-in and lead-out moves to every contour, skipping the contours where they would gouge the part.
+    ///
+    /// The waste is inside the contours if `waste_inside` is set, and outside otherwise.
+    pub fn with_leads(mut self, lead: &Lead, waste_inside: bool, resolution: f64) -> Self {
+        let enclosed = Enclosed::new(&self.contours);
+        let inside = |p: Coord| enclosed.contains(p);
+        let waste_normal = |line: &Line| enclosed.waste_normal(line, waste_inside, resolution);
 
         let mut contours = vec![];
+        let mut lead_in_lengths = vec![];
+
+        for contour in &self.contours {
+            let first = contour.lines().find(|line| line.delta().magnitude() > EPSILON);
+            let last = contour.lines().filter(|line| line.delta().magnitude() > EPSILON).last();
+
+            let (Some(first), Some(last)) = (first, last) else {
+                contours.push(contour.clone());
+                lead_in_lengths.push(0.0);
+                continue;
+            };
+
+            let (Some(n_in), Some(n_out)) = (waste_normal(&first), waste_normal(&last)) else {
+                contours.push(contour.clone());
+                lead_in_lengths.push(0.0);
+                continue;
+            };
+
+            let d_in = first.delta().try_normalize().unwrap();
+            let d_out = last.delta().try_normalize().unwrap();
+
+            let (lead_in, lead_out) = match *lead {
+                Lead::Line { length } => (
+                    vec![first.start + n_in * length],
+                    vec![last.end + n_out * length],
+                ),
+                Lead::Arc { radius } => {
+                    let quarter = |n: Coord, d: Coord| FRAC_PI_2 * d.wedge_product(n).signum();
+                    let angle = |v: Coord| v.y.atan2(v.x);
+
+                    let arc_in = EllipticalArc {
+                        center: first.start + n_in * radius,
+                        radii: Coord { x: radius, y: radius },
+                        rotation: 0.0,
+                        start_angle: angle(-d_in),
+                        sweep_angle: quarter(n_in, d_in),
+                    };
+
+                    let arc_out = EllipticalArc {
+                        center: last.end + n_out * radius,
+                        radii: Coord { x: radius, y: radius },
+                        rotation: 0.0,
+                        start_angle: angle(-n_out),
+                        sweep_angle: quarter(n_out, d_out),
+                    };
+
+                    // The last point of the lead-in is the start of the contour
+                    let mut lead_in = vec![arc_in.point_at(0.0)];
+                    lead_in.extend(arc_in.flatten(resolution));
+                    lead_in.pop();
+
+                    (lead_in, arc_out.flatten(resolution))
+                },
+            };
+
+            // The leads should stay in the waste and not cross any contour on the way to this one
+            let gouges = |lead: &[Coord], contact: Coord| {
+                let path = LineString::new(lead.iter().copied().chain(once(contact)).collect());
+
+                lead.iter().any(|&p| inside(p) != waste_inside)
+                    || (lead.len() > 1 && contour.intersects(&LineString::from(lead.to_vec())))
+                    || self.contours.iter().any(|c| c != contour && c.intersects(&path))
+            };
+
+            if gouges(&lead_in, first.start) || gouges(&lead_out, last.end) {
+                warn!("Leads at {:?} would gouge the part, skipping them", first.start);
+                contours.push(contour.clone());
+                lead_in_lengths.push(0.0);
+                continue;
+            }
 
-        for polygon in polygons_united {
-            contours.extend(polygon.interiors().into_iter().cloned());
-            contours.push(polygon.exterior().clone());
+            let lead_in_length = LineString::new(lead_in.iter().copied().chain(once(first.start)).collect()).length::<Euclidean>();
+            lead_in_lengths.push(lead_in_length);
+
+            contours.push(LineString::new(
+                lead_in.into_iter()
+                    .chain(contour.coords().copied())
+                    .chain(lead_out)
+                    .collect()
+            ));
         }
 
+        // Tabs are measured from the start of the contours
+        if let Some(tabs) = &mut self.tabs {
+            for (spans, shift) in tabs.spans.iter_mut().zip(&lead_in_lengths) {
+                for span in spans {
+                    span.0 += shift;
+                    span.1 += shift;
+                }
+            }
+        }
+
+        self.contours = contours;
+        self
+    }
+
+    /// Overcut the corners the bit can't reach, where the part has inside corners sharper than `max_angle`
+    pub fn with_relief(mut self, relief: &CornerRelief, waste_inside: bool, bit_radius: f64, resolution: f64) -> Self {
+        let enclosed = Enclosed::new(&self.contours);
+        let max_angle = relief.max_angle.to_radians();
+
+        // Where the bit has to go from the corner of the path to touch the corner of the part
+        let overcut = |prev: Coord, corner: Coord, next: Coord| -> Option<Coord> {
+            let line_in = Line::new(prev, corner);
+            let line_out = Line::new(corner, next);
+
+            let d_in = line_in.delta().try_normalize()?;
+            let d_out = line_out.delta().try_normalize()?;
+
+            // Normals pointing to the part
+            let n_in = -enclosed.waste_normal(&line_in, waste_inside, resolution)?;
+            let n_out = -enclosed.waste_normal(&line_out, waste_inside, resolution)?;
+
+            // Only the path turning away from the part leaves material in the corner
+            if d_out.dot_product(n_in) >= -EPSILON {
+                return None;
+            }
+
+            // Angle between the walls of the part, on the side of the waste
+            let angle = PI - d_in.find_angle(&d_out);
+            if angle > max_angle + EPSILON {
+                return None;
+            }
+
+            let bisector = (n_in + n_out).try_normalize()?;
+            let part_corner = corner + bisector * (bit_radius / (angle / 2.0).sin());
+            let w = part_corner - corner;
+
+            let dogbone = corner + bisector * (w.magnitude() - bit_radius);
+
+            match relief.style {
+                ReliefStyle::Dogbone => Some(dogbone),
+                ReliefStyle::TBone => {
+                    // Keep going straight until the edge of the bit reaches the corner
+                    let along = d_in.dot_product(w);
+                    let discriminant = along * along - w.magnitude_squared() + bit_radius * bit_radius;
+
+                    if discriminant < 0.0 {
+                        return Some(dogbone);
+                    }
+
+                    Some(corner + d_in * (along - discriminant.sqrt()))
+                },
+            }
+        };
+
+        let mut relieved = 0;
+
+        for contour in &mut self.contours {
+            if !contour.is_closed() || contour.0.len() < 4 {
+                continue;
+            }
+
+            let points = &contour.0;
+            let n = points.len() - 1;
+
+            let overcuts: Vec<_> = (0..n)
+                .map(|i| overcut(points[(i + n - 1) % n], points[i], points[i + 1]))
+                .collect();
+
+            let mut coords = vec![];
+
+            for i in 0..n {
+                coords.push(points[i]);
+
+                // The start of the contour is relieved at its end instead
+                if i > 0 && let Some(p) = overcuts[i] {
+                    coords.push(p);
+                    coords.push(points[i]);
+                }
+            }
+
+            coords.push(points[n]);
+
+            if let Some(p) = overcuts[0] {
+                coords.push(p);
+                coords.push(points[n]);
+            }
+
+            relieved += overcuts.iter().flatten().count();
+            contour.0 = coords;
+        }
+
+        debug!("Relieved {relieved} corners");
+
+        self
+    }
+
+    /// Contours clearing the inside of the polygons while keeping away from their holes, in the order they should be cut.
+    ///
+    /// The orientation only applies to the closed contours.
+    pub fn new_pocket(polygons: MultiPolygon, depths: Vec<f64>, bit_radius: f64, stepover: f64, strategy: &PocketStrategy, orientation: Option<Direction>, resolution: f64) -> Self {
+        let arc_resolution = geo_offset::ArcResolution::SegmentLength(resolution);
+
+        // The polygon collapses once the offset is larger than its inradius
+        let offset = |polygon: &Polygon, offset: f64| -> Option<MultiPolygon> {
+            match polygon.offset_with_arc_resolution(-offset, arc_resolution) {
+                Ok(rings) if !rings.0.is_empty() => Some(rings.simplify(&(resolution / 5.0))),
+                _ => None,
+            }
+        };
+
+        let rings = |polygon: &Polygon| {
+            let polygon = match orientation {
+                Some(orientation) => polygon.orient(orientation),
+                None => polygon.clone(),
+            };
+
+            polygon.interiors().iter()
+                .cloned()
+                .chain(once(polygon.exterior().clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut contours = vec![];
+        // The bit can go anywhere inside the outermost contours
+        let mut boundaries = vec![];
+
+        for polygon in unite(polygons.0, resolution) {
+            boundaries.extend(offset(&polygon, bit_radius).iter().flatten().flat_map(rings));
+
+            match strategy {
+                PocketStrategy::Concentric => {
+                    let mut polygon_contours = vec![];
+                    let mut level: Vec<_> = offset(&polygon, bit_radius).into_iter().flatten().collect();
+
+                    while !level.is_empty() {
+                        let mut next = vec![];
+
+                        for region in level {
+                            polygon_contours.extend(rings(&region));
+
+                            if let Some(inner) = offset(&region, stepover) {
+                                next.extend(inner);
+                                continue;
+                            }
+
+                            // The next ring would collapse, so the last one goes as deep as the region allows to reach its middle
+                            let (mut deepest, mut collapsed) = (0.0, stepover);
+                            while collapsed - deepest > resolution {
+                                let middle = (deepest + collapsed) / 2.0;
+                                if offset(&region, middle).is_some() {
+                                    deepest = middle;
+                                } else {
+                                    collapsed = middle;
+                                }
+                            }
+
+                            if deepest > 0.0 {
+                                polygon_contours.extend(offset(&region, deepest).iter().flatten().flat_map(rings));
+                            }
+                        }
+
+                        level = next;
+                    }
+
+                    // Start in the middle and finish on the boundary
+                    polygon_contours.reverse();
+                    contours.extend(polygon_contours);
+                },
+                PocketStrategy::Raster { angle, finish_contour } => {
+                    for region in offset(&polygon, bit_radius).into_iter().flatten() {
+                        contours.extend(hatch(&region, *angle, stepover));
+
+                        if *finish_contour {
+                            contours.extend(rings(&region));
+                        }
+                    }
+                },
+            }
+        }
+
+        debug!("Pocketing with {} contours", contours.len());
+
         Self {
             contours,
             depths,
+            tabs: None,
+            waste: Some(FabWaste::new(&boundaries, true, resolution)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FabCarveData {
+    /// Paths along the middle of the polygons, with the depth at each of their points
+    pub paths: Vec<(LineString, Vec<f64>)>,
+    /// Contours clearing the flat bottom where the polygons are wider than the bit at the maximum depth
+    pub clearing: Option<FabContourData>,
+}
+
+impl FabCarveData {
+    /// The flat bottom is cleared in passes of `clearing` depth, leaving ridges at most `scallop_height` tall
+    pub fn new(polygons: MultiPolygon, angle: f64, max_depth: Option<f64>, clearing: Option<f64>, scallop_height: f64, resolution: f64) -> Self {
+        let half_angle_tan = (angle / 2.0).to_radians().tan();
+
+        let polygons = unite(polygons.0, resolution);
+
+        let mut paths = vec![];
+
+        for polygon in &polygons {
+            let rings: Vec<_> = once(polygon.exterior()).chain(polygon.interiors()).collect();
+
+            for path in medial_axis(polygon, resolution) {
+                // The bit touches the nearest edge of the polygon on both sides
+                let depths = path.points()
+                    .map(|p| {
+                        let width = rings.iter()
+                            .map(|ring| Euclidean::distance(&p, *ring))
+                            .fold(f64::INFINITY, f64::min);
+
+                        let depth = width / half_angle_tan;
+                        max_depth.map_or(depth, |max| depth.min(max))
+                    })
+                    .collect();
+
+                paths.push((path, depths));
+            }
+        }
+
+        debug!("Carving with {} paths", paths.len());
+
+        let clearing = match (max_depth, clearing) {
+            (Some(max_depth), Some(depth_per_pass)) => {
+                let radius = max_depth * half_angle_tan;
+
+                // The pointed bit leaves a ridge between neighbouring passes
+                let stepover = 2.0 * scallop_height * half_angle_tan;
+
+                let polygons = MultiPolygon::new(polygons);
+                let depths = pass_depths(max_depth, depth_per_pass);
+                Some(FabContourData::new_pocket(polygons, depths, radius, stepover, &PocketStrategy::Concentric, None, resolution))
+            },
+            _ => None,
+        };
+
+        Self {
+            paths,
+            clearing,
         }
     }
 }
 
+#[derive(Debug)]
+pub struct FabFinish {
+    pub data: FabContourData,
+    pub feed: f64,
+}
+
 #[derive(Debug)]
 pub struct FabHoleData {
     pub holes: Vec<Hole>,
@@ -102,42 +613,159 @@ impl FabHoleData {
 #[derive(Debug)]
 pub enum FabOperation {
     Engrave(FabContourData),
-    Cut(FabContourData),
-    Drilling(FabHoleData),
+    Chamfer(FabContourData),
+    Cut {
+        data: FabContourData,
+        finish: Option<FabFinish>,
+    },
+    Pocket(FabContourData),
+    Carve(FabCarveData),
+    Drilling {
+        data: FabHoleData,
+        peck_depth: Option<f64>,
+        dwell: Option<f64>,
+        canned_cycle: bool,
+    },
     Boring {
         data: FabHoleData,
         depth_per_turn: f64,
         bit_radius: f64,
-    }
+        stepover: f64,
+    },
+    ThreadMilling {
+        data: FabHoleData,
+        pitch: f64,
+        thread_depth: f64,
+        thread: ThreadKind,
+        hand: Handedness,
+        bit_radius: f64,
+    },
 }
 
 impl FabOperation {
-    pub fn engrave_with_offset(polygons: MultiPolygon, depth: f64, offset: f64, resolution: f64) -> Self {
-        Self::Engrave(FabContourData::new(polygons, vec![depth], offset, resolution))
+    pub fn engrave_with_offset(polygons: MultiPolygon, depth: f64, offset: f64, direction: Option<&MillingDirection>, resolution: f64) -> Self {
+        let orientation = direction.map(|d| ring_orientation(d, false));
+        Self::Engrave(FabContourData::new(polygons, vec![depth], offset, orientation, resolution))
     }
 
-    pub fn cut(polygons: MultiPolygon, depth: f64, depth_per_pass: f64, bit_radius: f64, resolution: f64) -> Self {
-        let passes = (depth / depth_per_pass).floor() as usize;
+    pub fn cut(polygons: MultiPolygon, depth: f64, depth_per_pass: f64, side: &CutSide, tabs: Option<&TabsConfig>, lead: Option<&Lead>, direction: Option<&MillingDirection>, finish: Option<&FinishConfig>, relief: Option<&CornerRelief>, bit_radius: f64, resolution: f64) -> Self {
+        let depths = pass_depths(depth, depth_per_pass);
+
+        // Growing a polygon also shrinks its holes and vice versa
+        let offset = match side {
+            CutSide::Outside => bit_radius,
+            CutSide::Inside => -bit_radius,
+            CutSide::On => 0.0,
+        };
 
-        debug!("Cutting in {passes} passes: depth {depth}, depth_per_pass: {depth_per_pass}");
+        // Only the inside of the contours is waste when cutting a window
+        let waste_inside = matches!(side, CutSide::Inside);
+        let orientation = direction.map(|d| ring_orientation(d, waste_inside));
 
-        let depths: Vec<_> = (1..=passes)
-            .map(|pass| depth_per_pass * pass as f64)
-            .chain(once(depth))
-            .collect();
+        let finalize = |mut data: FabContourData| {
+            if !matches!(side, CutSide::On) {
+                data = data.with_waste(waste_inside, resolution);
+            }
+
+            if let Some(relief) = relief {
+                data = data.with_relief(relief, waste_inside, bit_radius, resolution);
+            }
+
+            if let Some(tabs) = tabs {
+                data = data.with_tabs(tabs, depth, bit_radius);
+            }
 
-        Self::Cut(FabContourData::new(polygons, depths, bit_radius, resolution))
+            if let Some(lead) = lead {
+                data = data.with_leads(lead, waste_inside, resolution);
+            }
+
+            data
+        };
+
+        let Some(finish) = finish else {
+            let data = FabContourData::new(polygons, depths, offset, orientation, resolution);
+
+            return Self::Cut {
+                data: finalize(data),
+                finish: None,
+            };
+        };
+
+        // The roughing passes stay further into the waste
+        let allowance = if waste_inside { -finish.allowance } else { finish.allowance };
+        let data = FabContourData::new(polygons.clone(), depths, offset + allowance, orientation, resolution);
+
+        let mut finish_data = FabContourData::new(polygons, vec![depth], offset, orientation, resolution);
+        if finish.reverse {
+            for contour in &mut finish_data.contours {
+                contour.0.reverse();
+            }
+        }
+
+        Self::Cut {
+            data: finalize(data),
+            finish: Some(FabFinish {
+                data: finalize(finish_data),
+                feed: finish.feed,
+            }),
+        }
+    }
+
+    pub fn pocket(polygons: MultiPolygon, depth: f64, depth_per_pass: f64, stepover: f64, strategy: &PocketStrategy, direction: Option<&MillingDirection>, bit_radius: f64, resolution: f64) -> Self {
+        let depths = pass_depths(depth, depth_per_pass);
+        let orientation = direction.map(|d| ring_orientation(d, true));
+        Self::Pocket(FabContourData::new_pocket(polygons, depths, bit_radius, stepover, strategy, orientation, resolution))
+    }
+
+    /// The flank of the bit touches the top edge `width` away from it when its tip is `clearance` away
+    pub fn chamfer(polygons: MultiPolygon, width: f64, clearance: f64, side: &CutSide, direction: Option<&MillingDirection>, angle: f64, resolution: f64) -> Self {
+        let depth = (width + clearance) / (angle / 2.0).to_radians().tan();
+
+        let (offset, waste_inside) = match side {
+            CutSide::Outside => (clearance, false),
+            CutSide::Inside => (-clearance, true),
+            CutSide::On => (0.0, false),
+        };
+
+        let orientation = direction.map(|d| ring_orientation(d, waste_inside));
+        let data = FabContourData::new(polygons, vec![depth], offset, orientation, resolution);
+
+        Self::Chamfer(match side {
+            CutSide::On => data,
+            _ => data.with_waste(waste_inside, resolution),
+        })
+    }
+
+    pub fn carve(polygons: MultiPolygon, angle: f64, max_depth: Option<f64>, clearing: Option<f64>, scallop_height: f64, resolution: f64) -> Self {
+        Self::Carve(FabCarveData::new(polygons, angle, max_depth, clearing, scallop_height, resolution))
     }
 
-    pub fn drill(holes: Vec<Hole>, depth: f64) -> Self {
-        Self::Drilling(FabHoleData::new(holes, depth))
+    pub fn drill(holes: Vec<Hole>, depth: f64, peck_depth: Option<f64>, dwell: Option<f64>, canned_cycle: bool) -> Self {
+        Self::Drilling {
+            data: FabHoleData::new(holes, depth),
+            peck_depth,
+            dwell,
+            canned_cycle,
+        }
     }
 
-    pub fn bore(holes: Vec<Hole>, depth: f64, depth_per_turn: f64, bit_radius: f64) -> Self {
+    pub fn bore(holes: Vec<Hole>, depth: f64, depth_per_turn: f64, stepover: f64, bit_radius: f64) -> Self {
         Self::Boring {
             data: FabHoleData::new(holes, depth),
             bit_radius,
             depth_per_turn,
+            stepover,
+        }
+    }
+
+    pub fn thread_mill(holes: Vec<Hole>, depth: f64, pitch: f64, thread_depth: f64, thread: ThreadKind, hand: Handedness, bit_radius: f64) -> Self {
+        Self::ThreadMilling {
+            data: FabHoleData::new(holes, depth),
+            pitch,
+            thread_depth,
+            thread,
+            hand,
+            bit_radius,
         }
     }
 }
@@ -161,6 +789,8 @@ fn hole_filter(hole: &Hole, radius_min: Option<f64>, radius_max: Option<f64>) ->
 #[derive(Debug)]
 pub struct FabData {
     pub feed: f64,
+    pub plunge_feed: f64,
+    pub entry: EntryStrategy,
     pub rpm: f64,
     pub operation: FabOperation,
 }
@@ -171,42 +801,144 @@ impl FabData {
 
         let JobConfig {
             feed,
+            plunge_feed,
+            entry,
             rpm,
             kind,
             bit_shape,
             ..
         } = job;
 
-        match kind {
-            EngraveContours { depth, offset } => {
-                ensure!(bit_shape == BitShape::V, "Unsupported bit shape: {:?}", bit_shape);
+        match entry {
+            EntryStrategy::Plunge => {},
+            EntryStrategy::Ramp { angle } => {
+                ensure!(angle > 0.0 && angle <= 90.0, "The ramp angle ({angle}) should be between 0 and 90 degrees");
+            },
+            EntryStrategy::Helix { radius, angle } => {
+                ensure!(radius > 0.0, "The helix radius ({radius}) should be greater than 0");
+                ensure!(angle > 0.0 && angle < 90.0, "The helix angle ({angle}) should be between 0 and 90 degrees");
+            },
+        }
+
+        let operation = match kind {
+            EngraveContours { depth, offset, direction } => {
+                ensure!(matches!(bit_shape, BitShape::V { .. }), "Unsupported bit shape: {:?}", bit_shape);
 
                 let polygons = primitives.polygons(config.resolution);
-                return Ok(FabData {
-                    operation: FabOperation::engrave_with_offset(polygons, depth, offset, config.resolution),
-                    feed,
-                    rpm
-                });
+                FabOperation::engrave_with_offset(polygons, depth, offset, direction.as_ref(), config.resolution)
             },
-            CutContours { depth, depth_per_pass } => {
-                let offset = match bit_shape {
+            CutContours { depth, depth_per_pass, side, tabs, lead, direction, finish, relief } => {
+                let bit_radius = match bit_shape {
                     BitShape::Square { radius } => radius,
                     _ => bail!("Unsupported bit shape: {:?}", bit_shape),
                 };
 
+                ensure!(depth > 0.0, "Depth should be greater than 0");
+                ensure!(depth_per_pass > 0.0, "Depth per pass should be greater than 0");
+
+                if let Some(tabs) = &tabs {
+                    ensure!(tabs.count.is_some() != tabs.spacing.is_some(), "Exactly one of tab count and spacing should be set");
+                    ensure!(tabs.width > 0.0, "Tab width should be greater than 0");
+                    ensure!(tabs.height > 0.0 && tabs.height < depth, "Tab height should be between 0 and the cut depth ({depth})");
+                }
+
+                match lead {
+                    Some(Lead::Arc { radius }) => ensure!(radius > 0.0, "Lead radius should be greater than 0"),
+                    Some(Lead::Line { length }) => ensure!(length > 0.0, "Lead length should be greater than 0"),
+                    None => {},
+                }
+
+                if let Some(finish) = &finish {
+                    ensure!(!matches!(side, CutSide::On), "A finishing pass needs an inside or outside cut");
+                    ensure!(finish.allowance > 0.0, "Finishing allowance should be greater than 0");
+                    ensure!(finish.feed > 0.0, "Finishing feed should be greater than 0");
+                }
+
+                if let Some(relief) = &relief {
+                    ensure!(relief.max_angle > 0.0 && relief.max_angle < 180.0, "Corner relief angle ({}) should be between 0 and 180 degrees", relief.max_angle);
+                }
+
                 let polygons = primitives.polygons(config.resolution);
-                return Ok(FabData {
-                    feed,
-                    rpm,
-                    operation: FabOperation::cut(polygons, depth, depth_per_pass, offset, config.resolution),
-                });
+                FabOperation::cut(polygons, depth, depth_per_pass, &side, tabs.as_ref(), lead.as_ref(), direction.as_ref(), finish.as_ref(), relief.as_ref(), bit_radius, config.resolution)
             },
-            DrillCircles { depth, radius_min, radius_max } => {
+            PocketContours { depth, depth_per_pass, stepover, strategy, direction } => {
                 let bit_radius = match bit_shape {
                     BitShape::Square { radius } => radius,
                     _ => bail!("Unsupported bit shape: {:?}", bit_shape),
                 };
 
+                ensure!(depth > 0.0, "Depth should be greater than 0");
+                ensure!(depth_per_pass > 0.0, "Depth per pass should be greater than 0");
+                ensure!(stepover > 0.0 && stepover <= bit_radius * 2.0, "The stepover ({stepover}) should be positive and at most the bit diameter ({})", bit_radius * 2.0);
+
+                let polygons = primitives.polygons(config.resolution);
+                FabOperation::pocket(polygons, depth, depth_per_pass, stepover, &strategy, direction.as_ref(), bit_radius, config.resolution)
+            },
+            ChamferContours { width, clearance, side, direction } => {
+                let angle = match bit_shape {
+                    BitShape::V { angle: Some(angle) } => angle,
+                    BitShape::V { angle: None } => bail!("Chamfering needs the angle of the V bit"),
+                    _ => bail!("Unsupported bit shape: {:?}", bit_shape),
+                };
+
+                ensure!(angle > 0.0 && angle < 180.0, "The V bit angle ({angle}) should be between 0 and 180 degrees");
+                ensure!(width > 0.0, "Chamfer width should be greater than 0");
+                ensure!(clearance >= 0.0, "Chamfer clearance should not be negative");
+                ensure!(clearance == 0.0 || !matches!(side, CutSide::On), "Chamfer clearance needs an inside or outside chamfer");
+
+                let polygons = primitives.polygons(config.resolution);
+                FabOperation::chamfer(polygons, width, clearance, &side, direction.as_ref(), angle, config.resolution)
+            },
+            VCarveContours { max_depth, clearing, depth_per_pass, scallop_height } => {
+                let angle = match bit_shape {
+                    BitShape::V { angle: Some(angle) } => angle,
+                    BitShape::V { angle: None } => bail!("V-carving needs the angle of the V bit"),
+                    _ => bail!("Unsupported bit shape: {:?}", bit_shape),
+                };
+
+                ensure!(angle > 0.0 && angle < 180.0, "The V bit angle ({angle}) should be between 0 and 180 degrees");
+
+                if let Some(max_depth) = max_depth {
+                    ensure!(max_depth > 0.0, "Maximum depth should be greater than 0");
+                }
+
+                ensure!(!clearing || max_depth.is_some(), "Clearing needs a maximum depth");
+                ensure!(!clearing || depth_per_pass.is_some(), "Clearing needs a depth per pass");
+
+                if let Some(depth_per_pass) = depth_per_pass {
+                    ensure!(depth_per_pass > 0.0, "Depth per pass should be greater than 0");
+                }
+
+                let scallop_height = scallop_height.unwrap_or(config.resolution);
+                ensure!(scallop_height > 0.0, "Scallop height should be greater than 0");
+
+                // The clearing passes are further apart than the width of the bit at the bottom otherwise
+                if let (true, Some(max_depth)) = (clearing, max_depth) {
+                    ensure!(scallop_height <= max_depth, "The scallop height ({scallop_height}) should be at most the maximum depth ({max_depth}) when clearing");
+                }
+
+                let clearing = if clearing { depth_per_pass } else { None };
+
+                let polygons = primitives.polygons(config.resolution);
+                FabOperation::carve(polygons, angle, max_depth, clearing, scallop_height, config.resolution)
+            },
+            DrillCircles { depth, radius_min, radius_max, peck_depth, dwell, canned_cycle } => {
+                let bit_radius = match bit_shape {
+                    BitShape::Square { radius } => radius,
+                    _ => bail!("Unsupported bit shape: {:?}", bit_shape),
+                };
+
+                if let Some(peck_depth) = peck_depth {
+                    ensure!(peck_depth > 0.0, "Peck depth should be greater than 0");
+                }
+
+                if let Some(dwell) = dwell {
+                    ensure!(dwell > 0.0, "Dwell should be greater than 0");
+                }
+
+                // G83 has no dwell at the bottom
+                ensure!(!(canned_cycle && peck_depth.is_some() && dwell.is_some()), "The canned drilling cycle can't both peck and dwell");
+
                 let holes: Vec<_> = primitives.holes()
                     .filter(|h| hole_filter(h, radius_min, radius_max))
                     .collect();
@@ -219,13 +951,9 @@ impl FabData {
                     })
                     .collect();
 
-                return Ok(FabData {
-                    feed,
-                    rpm,
-                    operation: FabOperation::drill(holes, depth),
-                });
+                FabOperation::drill(holes, depth, peck_depth, dwell, canned_cycle)
             },
-            BoreCircles { depth, depth_per_turn, radius_min, radius_max } => {
+            BoreCircles { depth, depth_per_turn, radius_min, radius_max, stepover } => {
                 let bit_radius = match bit_shape {
                     BitShape::Square { radius } => radius,
                     _ => bail!("Unsupported bit shape: {:?}", bit_shape),
@@ -238,15 +966,47 @@ impl FabData {
                 for hole in &holes {
                     let hole_radius = hole.radius;
                     ensure!(bit_radius + 0.1 <= hole_radius + EPSILON, "The hole (r={hole_radius}) is too small for boring with the bit (r={bit_radius}), consider increasing radius_min");
-                    ensure!(bit_radius * 2.0 >= hole_radius - EPSILON, "The hole (r={hole_radius}) is too big for boring with the bit (r={bit_radius}), consider reducing radius_max");
                 }
 
-                return Ok(FabData {
-                    feed,
-                    rpm,
-                    operation: FabOperation::bore(holes, depth, depth_per_turn, bit_radius),
-                });
+                // Holes wider than the bit diameter are cleared with several helices
+                let stepover = stepover.unwrap_or(bit_radius);
+                ensure!(stepover > 0.0 && stepover <= bit_radius * 2.0, "The stepover ({stepover}) should be positive and at most the bit diameter ({})", bit_radius * 2.0);
+
+                FabOperation::bore(holes, depth, depth_per_turn, stepover, bit_radius)
             },
-        }
+            ThreadMillCircles { depth, pitch, thread_depth, thread, hand, radius_min, radius_max } => {
+                let bit_radius = match bit_shape {
+                    BitShape::ThreadMill { radius } => radius,
+                    _ => bail!("Unsupported bit shape: {:?}", bit_shape),
+                };
+
+                ensure!(pitch > 0.0, "Thread pitch should be greater than 0");
+                ensure!(thread_depth > 0.0, "Thread depth should be greater than 0");
+                ensure!(thread_depth < bit_radius, "The thread depth ({thread_depth}) should be less than the bit radius ({bit_radius})");
+
+                let holes: Vec<_> = primitives.holes()
+                    .filter(|h| hole_filter(h, radius_min, radius_max))
+                    .collect();
+
+                for hole in &holes {
+                    let hole_radius = hole.radius;
+
+                    match thread {
+                        ThreadKind::Internal => ensure!(bit_radius < hole_radius - thread_depth, "The hole (r={hole_radius}) is too small for thread milling with the bit (r={bit_radius}), consider increasing radius_min"),
+                        ThreadKind::External => ensure!(thread_depth < hole_radius, "The boss (r={hole_radius}) is too small for the thread depth ({thread_depth}), consider increasing radius_min"),
+                    }
+                }
+
+                FabOperation::thread_mill(holes, depth, pitch, thread_depth, thread, hand, bit_radius)
+            },
+        };
+
+        Ok(FabData {
+            feed,
+            plunge_feed: plunge_feed.unwrap_or(feed),
+            entry,
+            rpm,
+            operation,
+        })
     }
 }
@@ -1,6 +1,12 @@
-use geo::{Coord, LineString, Vector2DOps};
+use std::f64::consts::TAU;
 
-use crate::{config::SharedFabConfig, fab::{FabContourData, FabData, FabHoleData, FabOperation, Hole}, io::gcode_generator::GCodeGenerator};
+use geo::{Coord, Euclidean, Length, LineString, Vector2DOps};
+
+use crate::{config::{EntryStrategy, Handedness, SharedFabConfig, ThreadKind}, shape::EPSILON, fab::{FabCarveData, FabContourData, FabData, FabFinish, FabHoleData, FabOperation, FabWaste, Hole}, io::gcode_generator::GCodeGenerator};
+
+
+/// How far above the bottom of the hole pecks start feeding again
+const PECK_CLEARANCE: f64 = 0.25;
 
 
 fn find_next_contour(contours: &Vec<&LineString>, now: Coord) -> usize {
@@ -12,31 +18,304 @@ fn find_next_contour(contours: &Vec<&LineString>, now: Coord) -> usize {
 }
 
 
-fn make_gcode_contours(config: &SharedFabConfig, data: &FabContourData, feed: f64, rpm: f64) -> String {
-    let mut contours: Vec<_> = data.contours.iter().collect();
+/// Points along the contour between the distances `from` and `to` from its start
+fn contour_section(contour: &LineString, from: f64, to: f64) -> Vec<Coord> {
+    let mut points = vec![];
 
-    let mut gcode = GCodeGenerator::new(feed, rpm, config.safe_height);
+    let mut distance = 0.0;
+    for line in contour.lines() {
+        let segment = line.delta().magnitude();
 
-    gcode.spindle_start_cwise();
+        if segment > EPSILON && distance + segment >= from {
+            if points.is_empty() {
+                points.push(line.start + line.delta() * ((from - distance).max(0.0) / segment));
+            }
+
+            if distance + segment >= to {
+                points.push(line.start + line.delta() * ((to - distance) / segment));
+                break;
+            }
+
+            points.push(line.end);
+        }
+
+        distance += segment;
+    }
+
+    if points.is_empty() {
+        points.extend(contour.0.first());
+    }
+
+    points
+}
+
+
+/// How to go down at the start of the contours, and where there's room for it
+#[derive(Clone, Copy)]
+struct Entry<'a> {
+    strategy: &'a EntryStrategy,
+    waste: Option<&'a FabWaste>,
+}
+
+
+/// Get from `from_depth` down to `depth` at the start of the path, ending there.
+///
+/// The bit is at `start_depth` and the passes before cleared the way down to `from_depth`.
+fn enter(gcode: &mut GCodeGenerator, path: &LineString, start_depth: f64, from_depth: f64, depth: f64, entry: Entry) {
+    let from_depth = from_depth.min(depth).max(start_depth);
+    if from_depth > start_depth {
+        gcode.move_z(-from_depth);
+    }
+
+    let descent = depth - from_depth;
+    let p0 = path.0[0];
+
+    match *entry.strategy {
+        EntryStrategy::Ramp { angle } if descent > EPSILON => {
+            let distance = descent / angle.to_radians().tan();
+
+            // Go back and forth along the start of the path as many times as needed
+            let prefix = contour_section(path, 0.0, path.length::<Euclidean>().min(distance / 2.0));
+            let prefix_length = LineString::new(prefix.clone()).length::<Euclidean>();
+
+            if prefix_length < EPSILON {
+                gcode.move_z(-depth);
+                return;
+            }
+
+            let trips = (distance / (prefix_length * 2.0) - EPSILON).ceil();
+            let slope = descent / (prefix_length * 2.0 * trips);
+
+            let mut z = from_depth;
+            let mut now = p0;
+            for _ in 0..trips as usize {
+                for &p in prefix.iter().skip(1).chain(prefix.iter().rev().skip(1)) {
+                    z += (p - now).magnitude() * slope;
+                    now = p;
+                    gcode.ramp_xyz(p.x, p.y, -z.min(depth));
+                }
+            }
+        },
+        EntryStrategy::Helix { radius, angle } if descent > EPSILON => {
+            // Around a center in the waste, tangent to the path at its start
+            let first = path.lines().find(|line| line.delta().magnitude() > EPSILON);
+            let helix = first.zip(entry.waste).and_then(|(line, waste)| {
+                let center = p0 + waste.normal(&line)? * radius;
+                waste.fits_circle(center, p0, radius).then_some((line, center))
+            });
+
+            // Ramp along the path instead where there's no room for the helix
+            let Some((line, center)) = helix else {
+                let ramp = Entry { strategy: &EntryStrategy::Ramp { angle }, waste: None };
+                enter(gcode, path, from_depth, from_depth, depth, ramp);
+                return;
+            };
+
+            let pitch = TAU * radius * angle.to_radians().tan();
+            let turns = (descent / pitch).ceil().max(1.0) as usize;
+            let offset = center - p0;
+
+            // Following the path at its start, which is counterclockwise when the center is on its left
+            if line.delta().wedge_product(offset) > 0.0 {
+                gcode.helix_ccwise(p0.x, p0.y, -depth, offset.x, offset.y, turns);
+            } else {
+                gcode.helix_cwise(p0.x, p0.y, -depth, offset.x, offset.y, turns);
+            }
+        },
+        // Already there
+        _ if from_depth > start_depth && descent <= EPSILON => {},
+        _ => gcode.move_z(-depth),
+    }
+}
+
+
+fn cut_contour(gcode: &mut GCodeGenerator, contour: &LineString, from_depth: f64, depth: f64, entry: Entry) {
+    let p0 = contour.0[0];
+    gcode.rapid(p0.x, p0.y);
+
+    gcode.engage();
+    enter(gcode, contour, 0.0, from_depth, depth, entry);
+
+    gcode.follow(&contour.0);
+
+    gcode.disengage();
+}
+
+
+/// Cut along the contour, lifting to `tab_depth` between the start and end distances of each tab.
+///
+/// Going back down after a tab is done like entering the contour, along the contour up to the next tab.
+fn cut_contour_with_tabs(gcode: &mut GCodeGenerator, contour: &LineString, from_depth: f64, depth: f64, entry: Entry, tab_depth: f64, tabs: &[(f64, f64)]) {
+    let in_tab = |distance: f64| tabs.iter().any(|&(start, end)| start <= distance && distance < end);
+
+    let contour_length = contour.length::<Euclidean>();
+    let up_to_next_tab = |distance: f64| {
+        let next = tabs.iter()
+            .map(|&(start, _)| start)
+            .filter(|&start| start > distance)
+            .fold(contour_length, f64::min);
+
+        LineString::new(contour_section(contour, distance, next))
+    };
+
+    let p0 = contour.0[0];
+    gcode.rapid(p0.x, p0.y);
+
+    gcode.engage();
+
+    let mut lifted = in_tab(0.0);
+    if lifted {
+        enter(gcode, contour, 0.0, from_depth, tab_depth, entry);
+    } else {
+        enter(gcode, &up_to_next_tab(0.0), 0.0, from_depth, depth, entry);
+    }
+
+    // The stretch between two tab boundaries goes through `follow` in one piece, so arcs can be fitted to it
+    let mut span = vec![p0];
+    let mut distance = 0.0;
+    for line in contour.lines() {
+        let length = line.delta().magnitude();
+
+        let mut boundaries: Vec<f64> = tabs.iter()
+            .flat_map(|&(start, end)| [start, end])
+            .filter(|&b| b > distance && b < distance + length)
+            .collect();
+        boundaries.sort_by(f64::total_cmp);
+
+        for b in boundaries {
+            if in_tab(b) == lifted {
+                continue;
+            }
+
+            let p = line.start + line.delta() * ((b - distance) / length);
+            span.push(p);
+            gcode.follow(&span);
+            span = vec![p];
+
+            lifted = !lifted;
+            if lifted {
+                gcode.move_z(-tab_depth);
+            } else {
+                enter(gcode, &up_to_next_tab(b), tab_depth, from_depth, depth, entry);
+            }
+        }
+
+        span.push(line.end);
+        distance += length;
+    }
+
+    gcode.follow(&span);
+    gcode.disengage();
+}
+
+
+/// Cut the contours one after another, going down through all the depths on each
+fn cut_contours(gcode: &mut GCodeGenerator, data: &FabContourData, entry: &EntryStrategy, start_depth: f64, now: &mut Coord) {
+    let entry = Entry { strategy: entry, waste: data.waste.as_ref() };
+    let mut contours: Vec<_> = data.contours.iter().enumerate().collect();
 
-    let mut now = Coord { x: 0.0, y: 0.0 };
     while !contours.is_empty() {
-        let contour = contours.remove(find_next_contour(&contours, now));
-        now = contour.0[0];
+        let starts: Vec<_> = contours.iter().map(|&(_, c)| c).collect();
+        let (i, contour) = contours.remove(find_next_contour(&starts, *now));
+        *now = contour.0[0];
 
+        let mut from_depth = start_depth;
         for &depth in &data.depths {
-            let mut points = contour.coords();
-            let p0 = points.next().unwrap();
-            gcode.rapid(p0.x, p0.y);
+            match &data.tabs {
+                Some(tabs) if depth > tabs.depth => cut_contour_with_tabs(gcode, contour, from_depth, depth, entry, tabs.depth, &tabs.spans[i]),
+                _ => cut_contour(gcode, contour, from_depth, depth, entry),
+            }
+            from_depth = depth;
+        }
+    }
+}
+
+
+fn make_gcode_contours(config: &SharedFabConfig, data: &FabContourData, finish: Option<&FabFinish>, entry: &EntryStrategy, feed: f64, plunge_feed: f64, rpm: f64) -> String {
+    let mut gcode = GCodeGenerator::new(config, feed, plunge_feed, rpm);
+
+    gcode.spindle_start_cwise();
+
+    let mut now = Coord { x: 0.0, y: 0.0 };
+    cut_contours(&mut gcode, data, entry, 0.0, &mut now);
+
+    // The roughing passes already cleared the way down
+    if let Some(finish) = finish {
+        let start_depth = data.depths.last().copied().unwrap_or(0.0);
+
+        gcode.set_feed(finish.feed);
+        cut_contours(&mut gcode, &finish.data, entry, start_depth, &mut now);
+    }
+
+    gcode.spindle_stop();
+
+    gcode.into_string()
+}
+
+
+fn make_gcode_pocket(config: &SharedFabConfig, data: &FabContourData, entry: &EntryStrategy, feed: f64, plunge_feed: f64, rpm: f64) -> String {
+    let mut gcode = GCodeGenerator::new(config, feed, plunge_feed, rpm);
+
+    gcode.spindle_start_cwise();
+
+    // Clear the whole pocket at each depth before going deeper,
+    // the contours are already in the order they should be cut in
+    let entry = Entry { strategy: entry, waste: data.waste.as_ref() };
+
+    let mut from_depth = 0.0;
+    for &depth in &data.depths {
+        for contour in &data.contours {
+            cut_contour(&mut gcode, contour, from_depth, depth, entry);
+        }
+        from_depth = depth;
+    }
+
+    gcode.spindle_stop();
 
-            gcode.engage();
-            gcode.move_z(-depth);
+    gcode.into_string()
+}
+
+
+fn make_gcode_carve(config: &SharedFabConfig, data: &FabCarveData, entry: &EntryStrategy, feed: f64, plunge_feed: f64, rpm: f64) -> String {
+    let mut paths: Vec<_> = data.paths.iter().collect();
+
+    let mut gcode = GCodeGenerator::new(config, feed, plunge_feed, rpm);
+
+    gcode.spindle_start_cwise();
 
-            while let Some(p) = points.next() {
-                gcode.move_xy(p.x, p.y);
+    let mut now = Coord { x: 0.0, y: 0.0 };
+    while !paths.is_empty() {
+        let starts: Vec<_> = paths.iter().map(|(path, _)| path).collect();
+        let (path, depths) = paths.remove(find_next_contour(&starts, now));
+        now = path.0[0];
+
+        gcode.rapid(now.x, now.y);
+        gcode.engage();
+        gcode.move_z(-depths[0]);
+
+        // Going deeper is done at the plunge feed
+        for (p, pair) in path.coords().skip(1).zip(depths.windows(2)) {
+            let (previous, depth) = (pair[0], pair[1]);
+
+            if depth > previous {
+                gcode.ramp_xyz(p.x, p.y, -depth);
+            } else {
+                gcode.move_xyz(p.x, p.y, -depth);
             }
+        }
+
+        gcode.disengage();
+    }
+
+    if let Some(clearing) = &data.clearing {
+        let entry = Entry { strategy: entry, waste: clearing.waste.as_ref() };
 
-            gcode.disengage();
+        let mut from_depth = 0.0;
+        for &depth in &clearing.depths {
+            for contour in &clearing.contours {
+                cut_contour(&mut gcode, contour, from_depth, depth, entry);
+            }
+            from_depth = depth;
         }
     }
 
@@ -55,10 +334,13 @@ fn find_next_hole(holes: &Vec<&Hole>, now: Coord) -> usize {
 }
 
 
-fn make_gcode_drilling(config: &SharedFabConfig, data: &FabHoleData, feed: f64, rpm: f64) -> String {
+fn make_gcode_drilling(config: &SharedFabConfig, data: &FabHoleData, peck_depth: Option<f64>, dwell: Option<f64>, canned_cycle: bool, feed: f64, plunge_feed: f64, rpm: f64) -> String {
     let mut holes: Vec<_> = data.holes.iter().collect();
 
-    let mut gcode = GCodeGenerator::new(feed, rpm, config.safe_height);
+    let mut gcode = GCodeGenerator::new(config, feed, plunge_feed, rpm);
+
+    // Drill with separate moves on controllers without the cycles
+    let canned_cycle = canned_cycle && gcode.canned_cycles();
 
     gcode.spindle_start_cwise();
 
@@ -67,22 +349,64 @@ fn make_gcode_drilling(config: &SharedFabConfig, data: &FabHoleData, feed: f64,
         let hole = holes.remove(find_next_hole(&holes, now));
         now = hole.center;
 
+        if canned_cycle {
+            gcode.drill_cycle(hole.center.x, hole.center.y, -data.depth, peck_depth, dwell);
+            continue;
+        }
+
         gcode.rapid(hole.center.x, hole.center.y);
         gcode.engage();
+
+        // Pull out to clear the chips after each peck, then rapid back down to just above where it ended
+        if let Some(peck_depth) = peck_depth {
+            let mut depth = peck_depth;
+            while depth < data.depth - EPSILON {
+                gcode.move_z(-depth);
+                gcode.retract_z(config.safe_height);
+                gcode.rapid_z((PECK_CLEARANCE - depth).min(0.0));
+                depth += peck_depth;
+            }
+        }
+
         gcode.move_z(-data.depth);
+
+        if let Some(dwell) = dwell {
+            gcode.dwell(dwell);
+        }
+
         gcode.disengage();
     }
 
+    if canned_cycle {
+        gcode.drill_cycle_end();
+    }
+
     gcode.spindle_stop();
 
     gcode.into_string()
 }
 
 
-fn make_gcode_boring(config: &SharedFabConfig, data: &FabHoleData, depth_per_turn: f64, bit_radius: f64, feed: f64, rpm: f64) -> String {
+/// Go down a helix around the center and clean up the bottom with a full circle
+fn bore_helix(gcode: &mut GCodeGenerator, center: Coord, radius: f64, depth: f64, depth_per_turn: f64) {
+    let offset = Coord {
+        x: radius,
+        y: 0.0,
+    };
+
+    let helix = center + offset;
+
+    let turns = (depth / depth_per_turn).ceil().max(1.0) as usize;
+
+    gcode.helix_ccwise(helix.x, helix.y, -depth, -offset.x, -offset.y, turns);
+    gcode.arc_ccwise(helix.x, helix.y, -offset.x, -offset.y);
+}
+
+
+fn make_gcode_boring(config: &SharedFabConfig, data: &FabHoleData, depth_per_turn: f64, stepover: f64, bit_radius: f64, feed: f64, plunge_feed: f64, rpm: f64) -> String {
     let mut holes: Vec<_> = data.holes.iter().collect();
 
-    let mut gcode = GCodeGenerator::new(feed, rpm, config.safe_height);
+    let mut gcode = GCodeGenerator::new(config, feed, plunge_feed, rpm);
 
     gcode.spindle_start_ccwise();
 
@@ -91,19 +415,90 @@ fn make_gcode_boring(config: &SharedFabConfig, data: &FabHoleData, depth_per_tur
         let hole = holes.remove(find_next_hole(&holes, now));
         now = hole.center;
 
-        let offset = Coord {
-            x: hole.radius - bit_radius,
-            y: 0.0,
+        let radius = hole.radius - bit_radius;
+
+        // A helix no wider than the bit leaves no core, the next ones widen the hole up to the final one
+        let mut helices = vec![];
+        let mut r = bit_radius;
+        while r < radius - EPSILON {
+            helices.push(r);
+            r += stepover;
+        }
+        helices.push(radius);
+
+        for (i, &r) in helices.iter().enumerate() {
+            let start = hole.center + Coord { x: r, y: 0.0 };
+
+            if i == 0 {
+                gcode.rapid(start.x, start.y);
+                gcode.engage();
+            } else {
+                // The inside is already cleared
+                gcode.retract_z(0.0);
+                gcode.move_xy(start.x, start.y);
+            }
+
+            bore_helix(&mut gcode, hole.center, r, data.depth, depth_per_turn);
+        }
+
+        gcode.disengage();
+    }
+
+    gcode.spindle_stop();
+
+    gcode.into_string()
+}
+
+
+/// Thread the holes one turn of the helix at a time.
+///
+/// Internal threads are cut from the bottom up and external ones from the top down,
+/// so right hand threads go counterclockwise inside holes and clockwise around bosses.
+fn make_gcode_thread_milling(config: &SharedFabConfig, data: &FabHoleData, pitch: f64, thread_depth: f64, thread: &ThreadKind, hand: &Handedness, bit_radius: f64, feed: f64, plunge_feed: f64, rpm: f64) -> String {
+    let mut holes: Vec<_> = data.holes.iter().collect();
+
+    let mut gcode = GCodeGenerator::new(config, feed, plunge_feed, rpm);
+
+    gcode.spindle_start_cwise();
+
+    let turns = (data.depth / pitch - EPSILON).ceil().max(1.0) as usize;
+
+    let mut now = Coord { x: 0.0, y: 0.0 };
+    while !holes.is_empty() {
+        let hole = holes.remove(find_next_hole(&holes, now));
+        now = hole.center;
+
+        let (orbit, approach, z_start, z_step) = match thread {
+            ThreadKind::Internal => (hole.radius - bit_radius, 0.0, -data.depth, pitch),
+            ThreadKind::External => (hole.radius - thread_depth + bit_radius, hole.radius + bit_radius, 0.0, -pitch),
         };
 
-        let helix = hole.center + offset;
+        let ccwise = matches!((thread, hand), (ThreadKind::Internal, Handedness::Right) | (ThreadKind::External, Handedness::Left));
 
-        let turns = (data.depth / depth_per_turn) as usize;
+        let approach = hole.center + Coord { x: approach, y: 0.0 };
+        let offset = Coord { x: orbit, y: 0.0 };
+        let helix = hole.center + offset;
 
-        gcode.rapid(helix.x, helix.y);
+        gcode.rapid(approach.x, approach.y);
         gcode.engage();
-        gcode.helix_ccwise(helix.x, helix.y, -data.depth, -offset.x, -offset.y, turns);
-        gcode.arc_ccwise(helix.x, helix.y, -offset.x, -offset.y);
+        if z_start < 0.0 {
+            gcode.move_z(z_start);
+        }
+        gcode.move_xy(helix.x, helix.y);
+
+        for turn in 1..=turns {
+            // The last turn of external threads stops at the depth, instead of going into the base of the boss
+            let z = (z_start + z_step * turn as f64).max(-data.depth);
+
+            if ccwise {
+                gcode.helix_ccwise(helix.x, helix.y, z, -offset.x, -offset.y, 1);
+            } else {
+                gcode.helix_cwise(helix.x, helix.y, z, -offset.x, -offset.y, 1);
+            }
+        }
+
+        // Back away from the thread before leaving
+        gcode.move_xy(approach.x, approach.y);
         gcode.disengage();
     }
 
@@ -115,17 +510,42 @@ fn make_gcode_boring(config: &SharedFabConfig, data: &FabHoleData, depth_per_tur
 
 pub fn make_gcode(config: &SharedFabConfig, fd: &FabData) -> String {
     let feed = fd.feed;
+    let plunge_feed = fd.plunge_feed;
     let rpm = fd.rpm;
     match &fd.operation {
         | FabOperation::Engrave(data)
-        | FabOperation::Cut(data) => make_gcode_contours(config, data, feed, rpm),
+        | FabOperation::Chamfer(data) => make_gcode_contours(config, data, None, &fd.entry, feed, plunge_feed, rpm),
+
+        FabOperation::Cut {
+            data,
+            finish,
+        } => make_gcode_contours(config, data, finish.as_ref(), &fd.entry, feed, plunge_feed, rpm),
+
+        FabOperation::Pocket(data) => make_gcode_pocket(config, data, &fd.entry, feed, plunge_feed, rpm),
 
-        FabOperation::Drilling(data) => make_gcode_drilling(config, data, feed, rpm),
+        FabOperation::Carve(data) => make_gcode_carve(config, data, &fd.entry, feed, plunge_feed, rpm),
+
+        FabOperation::Drilling {
+            data,
+            peck_depth,
+            dwell,
+            canned_cycle,
+        } => make_gcode_drilling(config, data, *peck_depth, *dwell, *canned_cycle, feed, plunge_feed, rpm),
 
         FabOperation::Boring {
             data,
             depth_per_turn,
             bit_radius,
-        } => make_gcode_boring(config, data, *depth_per_turn, *bit_radius, feed, rpm),
+            stepover,
+        } => make_gcode_boring(config, data, *depth_per_turn, *stepover, *bit_radius, feed, plunge_feed, rpm),
+
+        FabOperation::ThreadMilling {
+            data,
+            pitch,
+            thread_depth,
+            thread,
+            hand,
+            bit_radius,
+        } => make_gcode_thread_milling(config, data, *pitch, *thread_depth, thread, hand, *bit_radius, feed, plunge_feed, rpm),
     }
 }
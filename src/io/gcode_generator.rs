@@ -18,6 +18,12 @@ macro_rules! always_assert_ne {
     }
 }
 
+use std::f64::consts::TAU;
+
+use geo::{Coord, Vector2DOps};
+
+use crate::{config::SharedFabConfig, io::post::{post_processor, PostProcessor}, shape::{fit_arcs, PathSegment, EPSILON}};
+
 #[derive(Debug, PartialEq)]
 enum GCodeState {
     Stopped,
@@ -27,37 +33,79 @@ enum GCodeState {
 
 use GCodeState::*;
 
+/// Moves that go down are done at the plunge feed, the rest at the normal feed.
+///
+/// The commands are written for the dialect set in the config.
 pub struct GCodeGenerator {
+    post: Box<dyn PostProcessor>,
     safe_height: f64,
+    feed: f64,
+    plunge_feed: f64,
+    current_feed: f64,
+    rpm: f64,
+    arc_tolerance: Option<f64>,
 
     state: GCodeState,
+    now: Coord,
+    z: f64,
     actions: Vec<String>,
 }
 
 impl GCodeGenerator {
-    pub fn new(feed: f64, rpm: f64, safe_height: f64) -> Self {
+    pub fn new(config: &SharedFabConfig, feed: f64, plunge_feed: f64, rpm: f64) -> Self {
+        let post = post_processor(&config.dialect);
+        let safe_height = config.safe_height;
+
+        let mut actions = post.header();
+        actions.push(format!("G1 F{feed}")); // Feed
+        actions.push(format!("G0 Z{safe_height}")); // Go to safe height
+
         Self {
+            post,
             safe_height,
+            feed,
+            plunge_feed,
+            current_feed: feed,
+            rpm,
+            arc_tolerance: config.arc_tolerance,
             state: GCodeState::Stopped,
-            actions: vec![
-                format!("G90"), // Absolute
-                format!("G21"), // Use millimeters
-                format!("G1 F{feed}"), // Feed
-                format!("S{rpm}"), // Spindle
-                format!("G0 Z{safe_height}"), // Go to safe height
-            ],
+            now: Coord::zero(),
+            z: safe_height,
+            actions,
+        }
+    }
+
+    /// Whether `drill_cycle` can be used
+    pub fn canned_cycles(&self) -> bool {
+        self.post.canned_cycles()
+    }
+
+    /// Change the feed of the following moves that don't go down
+    pub fn set_feed(&mut self, feed: f64) {
+        self.feed = feed;
+    }
+
+    /// The F word to add to a move, if the feed has to change for it
+    fn feed_word(&mut self, plunge: bool) -> String {
+        let feed = if plunge { self.plunge_feed } else { self.feed };
+
+        if feed == self.current_feed {
+            return String::new();
         }
+
+        self.current_feed = feed;
+        format!(" F{feed}")
     }
 
     pub fn spindle_start_cwise(&mut self) {
         always_assert_eq!(self.state, Stopped);
-        self.actions.push(format!("M3"));
+        self.actions.push(self.post.spindle_start(self.rpm, false));
         self.state = SpinningDisengaged;
     }
 
     pub fn spindle_start_ccwise(&mut self) {
         always_assert_eq!(self.state, Stopped);
-        self.actions.push(format!("M4"));
+        self.actions.push(self.post.spindle_start(self.rpm, true));
         self.state = SpinningDisengaged;
     }
 
@@ -70,58 +118,201 @@ impl GCodeGenerator {
     pub fn engage(&mut self) {
         always_assert_eq!(self.state, SpinningDisengaged);
         self.actions.push(format!("G1 Z0"));
+        self.z = 0.0;
         self.state = SpinningEngaged;
     }
 
     pub fn disengage(&mut self) {
         always_assert_eq!(self.state, SpinningEngaged);
         self.actions.push(format!("G1 Z{}", self.safe_height));
+        self.z = self.safe_height;
         self.state = SpinningDisengaged;
     }
 
     pub fn rapid(&mut self, x: f64, y: f64) {
         always_assert_ne!(self.state, SpinningEngaged);
         self.actions.push(format!("G0 X{x} Y{y}"));
+        self.now = Coord { x, y };
     }
 
     pub fn move_xy(&mut self, x: f64, y: f64) {
         always_assert_eq!(self.state, SpinningEngaged);
-        self.actions.push(format!("G1 X{x} Y{y}"));
+        let f = self.feed_word(false);
+        self.actions.push(format!("G1 X{x} Y{y}{f}"));
+        self.now = Coord { x, y };
     }
 
     pub fn move_z(&mut self, z: f64) {
         always_assert_eq!(self.state, SpinningEngaged);
-        self.actions.push(format!("G1 Z{z}"));
+        let f = self.feed_word(true);
+        self.actions.push(format!("G1 Z{z}{f}"));
+        self.z = z;
     }
 
-    pub fn helix_ccwise(&mut self, end_x: f64, end_y: f64, end_z: f64, offset_x: f64, offset_y: f64, turns: usize) {
+    pub fn move_xyz(&mut self, x: f64, y: f64, z: f64) {
         always_assert_eq!(self.state, SpinningEngaged);
+        let f = self.feed_word(false);
+        self.actions.push(format!("G1 X{x} Y{y} Z{z}{f}"));
+        self.now = Coord { x, y };
+        self.z = z;
+    }
 
-        // 1. Select the axis
-        // - G17 - Z-axis, XY-plane
-        // - G18 - Y-axis, XZ-plane
-        // - G19 - X-axis, YZ-plane
+    /// Move going down at an angle
+    pub fn ramp_xyz(&mut self, x: f64, y: f64, z: f64) {
+        always_assert_eq!(self.state, SpinningEngaged);
+        let f = self.feed_word(true);
+        self.actions.push(format!("G1 X{x} Y{y} Z{z}{f}"));
+        self.now = Coord { x, y };
+        self.z = z;
+    }
+
+    /// Move up without cutting
+    pub fn retract_z(&mut self, z: f64) {
+        always_assert_eq!(self.state, SpinningEngaged);
+        self.actions.push(format!("G0 Z{z}"));
+        self.z = z;
+    }
 
-        self.actions.push(format!("G17"));
+    /// Move down without cutting, where the material was already cut
+    pub fn rapid_z(&mut self, z: f64) {
+        always_assert_eq!(self.state, SpinningEngaged);
+        self.actions.push(format!("G0 Z{z}"));
+        self.z = z;
+    }
 
-        // 2. Do the move
-        // As viewed from the positive end of the axis:
+    pub fn dwell(&mut self, seconds: f64) {
+        always_assert_eq!(self.state, SpinningEngaged);
+        self.actions.push(self.post.dwell(seconds));
+    }
+
+    /// Drill a hole with a canned cycle, starting to feed at the safe height and going back to it.
+    ///
+    /// - G81 - straight down
+    /// - G82 - straight down and dwell at the bottom
+    /// - G83 - pecking, retracting fully after each peck
+    pub fn drill_cycle(&mut self, x: f64, y: f64, z: f64, peck: Option<f64>, dwell: Option<f64>) {
+        always_assert_eq!(self.state, SpinningDisengaged);
+
+        let r = self.safe_height;
+        let f = self.feed_word(true);
+
+        self.actions.push(match (peck, dwell) {
+            (Some(q), _) => format!("G98 G83 X{x} Y{y} Z{z} R{r} Q{q}{f}"),
+            (None, Some(p)) => format!("G98 G82 X{x} Y{y} Z{z} R{r} P{p}{f}"),
+            (None, None) => format!("G98 G81 X{x} Y{y} Z{z} R{r}{f}"),
+        });
+
+        self.now = Coord { x, y };
+    }
+
+    pub fn drill_cycle_end(&mut self) {
+        always_assert_eq!(self.state, SpinningDisengaged);
+        self.actions.push(format!("G80"));
+    }
+
+    /// Helix from the current position to the end, around the center at `offset` from the current position,
+    /// the last of the `turns` ending at the end
+    fn helix(&mut self, ccwise: bool, end: Coord, end_z: f64, offset: Coord, turns: usize) {
+        always_assert_eq!(self.state, SpinningEngaged);
+
+        // As viewed from the positive end of the Z axis:
         // - G2 - clockwise
         // - G3 - counterclockwise
+        let g = if ccwise { 3 } else { 2 };
+
+        // Even a helix ending where it starts goes around once
+        let turns = turns.max(1);
+
+        if self.post.helix_turns() {
+            let f = self.feed_word(true);
+            self.actions.push(format!("G{g} X{} Y{} Z{end_z} I{} J{} P{turns}{f}", end.x, end.y, offset.x, offset.y));
+        } else {
+            // Full circles back to the start, then the rest of the way to the end,
+            // going down evenly along the whole helix
+            let start = self.now;
+            let center = start + offset;
+
+            let (from, to) = (start - center, end - center);
+            let mut angle = from.wedge_product(to).atan2(from.dot_product(to));
+            if !ccwise {
+                angle = -angle;
+            }
+            if angle <= EPSILON {
+                angle += TAU;
+            }
+
+            let total = TAU * (turns - 1) as f64 + angle;
+            let descent = (end_z - self.z) / total;
+            let start_z = self.z;
+
+            for turn in 1..turns {
+                let z = start_z + descent * TAU * turn as f64;
+                let f = self.feed_word(true);
+                self.actions.push(format!("G{g} X{} Y{} Z{z} I{} J{}{f}", start.x, start.y, offset.x, offset.y));
+            }
+
+            let f = self.feed_word(true);
+            self.actions.push(format!("G{g} X{} Y{} Z{end_z} I{} J{}{f}", end.x, end.y, offset.x, offset.y));
+        }
+
+        self.now = end;
+        self.z = end_z;
+    }
 
-        self.actions.push(format!("G3 X{end_x} Y{end_y} Z{end_z} I{offset_x} J{offset_y} P{turns}"));
+    pub fn helix_ccwise(&mut self, end_x: f64, end_y: f64, end_z: f64, offset_x: f64, offset_y: f64, turns: usize) {
+        self.helix(true, Coord { x: end_x, y: end_y }, end_z, Coord { x: offset_x, y: offset_y }, turns);
+    }
+
+    pub fn helix_cwise(&mut self, end_x: f64, end_y: f64, end_z: f64, offset_x: f64, offset_y: f64, turns: usize) {
+        self.helix(false, Coord { x: end_x, y: end_y }, end_z, Coord { x: offset_x, y: offset_y }, turns);
     }
 
     pub fn arc_ccwise(&mut self, end_x: f64, end_y: f64, offset_x: f64, offset_y: f64) {
         always_assert_eq!(self.state, SpinningEngaged);
 
-        self.actions.push(format!("G17"));
-        self.actions.push(format!("G3 X{end_x} Y{end_y} I{offset_x} J{offset_y}"));
+        let f = self.feed_word(false);
+        self.actions.push(format!("G3 X{end_x} Y{end_y} I{offset_x} J{offset_y}{f}"));
+        self.now = Coord { x: end_x, y: end_y };
+    }
+
+    pub fn arc_cwise(&mut self, end_x: f64, end_y: f64, offset_x: f64, offset_y: f64) {
+        always_assert_eq!(self.state, SpinningEngaged);
+
+        let f = self.feed_word(false);
+        self.actions.push(format!("G2 X{end_x} Y{end_y} I{offset_x} J{offset_y}{f}"));
+        self.now = Coord { x: end_x, y: end_y };
+    }
+
+    /// Move from the first of the points through the rest of them, with arcs where they fit within the arc tolerance
+    pub fn follow(&mut self, points: &[Coord]) {
+        let Some(tolerance) = self.arc_tolerance else {
+            for p in points.iter().skip(1) {
+                self.move_xy(p.x, p.y);
+            }
+            return;
+        };
+
+        let mut now = points[0];
+        for segment in fit_arcs(points, tolerance) {
+            match segment {
+                PathSegment::Line { end } => self.move_xy(end.x, end.y),
+                PathSegment::Arc { end, center, ccwise: true } => self.arc_ccwise(end.x, end.y, center.x - now.x, center.y - now.y),
+                PathSegment::Arc { end, center, ccwise: false } => self.arc_cwise(end.x, end.y, center.x - now.x, center.y - now.y),
+            }
+
+            now = match segment {
+                PathSegment::Line { end } | PathSegment::Arc { end, .. } => end,
+            };
+        }
     }
 
     pub fn into_string(mut self) -> String {
         always_assert_eq!(self.state, Stopped);
-        self.actions.push(format!("M2"));
-        self.actions.join("\n")
+
+        if let Some(end) = self.post.program_end() {
+            self.actions.push(end);
+        }
+
+        self.actions.join(self.post.line_ending())
     }
 }
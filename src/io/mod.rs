@@ -1,4 +1,7 @@
 pub mod gcode;
 pub mod gcode_generator;
+pub mod post;
+pub mod svg_attributes;
 pub mod svg_input;
 pub mod svg_output;
+pub mod svg_transform;
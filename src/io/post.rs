@@ -0,0 +1,127 @@
+use crate::config::Dialect;
+
+
+/// What a controller accepts, where it differs between G-code dialects
+pub trait PostProcessor {
+    /// Lines at the start of the program, before the feed and the first move
+    fn header(&self) -> Vec<String> {
+        vec![
+            format!("G90"), // Absolute
+            format!("G21"), // Use millimeters
+            format!("G17"), // Arcs in the XY plane
+        ]
+    }
+
+    /// Whether G2/G3 take a P word for the number of turns, otherwise helices are cut one turn per move
+    fn helix_turns(&self) -> bool {
+        false
+    }
+
+    /// Whether the G81/G82/G83 drilling cycles are supported, otherwise holes are drilled with separate moves
+    fn canned_cycles(&self) -> bool {
+        false
+    }
+
+    fn spindle_start(&self, rpm: f64, ccwise: bool) -> String {
+        if ccwise {
+            format!("M4 S{rpm}")
+        } else {
+            format!("M3 S{rpm}")
+        }
+    }
+
+    fn dwell(&self, seconds: f64) -> String {
+        format!("G4 P{seconds}")
+    }
+
+    fn program_end(&self) -> Option<String> {
+        Some(format!("M2"))
+    }
+
+    fn line_ending(&self) -> &'static str {
+        "\n"
+    }
+}
+
+
+pub struct LinuxCnc;
+
+impl PostProcessor for LinuxCnc {
+    fn helix_turns(&self) -> bool {
+        true
+    }
+
+    fn canned_cycles(&self) -> bool {
+        true
+    }
+}
+
+
+/// No canned cycles, and P on arcs is an error
+pub struct Grbl;
+
+impl PostProcessor for Grbl {}
+
+
+pub struct Mach3;
+
+impl PostProcessor for Mach3 {
+    fn canned_cycles(&self) -> bool {
+        true
+    }
+
+    /// Rewinds the program
+    fn program_end(&self) -> Option<String> {
+        Some(format!("M30"))
+    }
+
+    fn line_ending(&self) -> &'static str {
+        "\r\n"
+    }
+}
+
+
+/// Helices are cut one turn per move, and there is no program end command
+pub struct Marlin;
+
+impl PostProcessor for Marlin {
+    fn header(&self) -> Vec<String> {
+        vec![
+            format!("G90"), // Absolute, millimeters are the only units unless inch mode is built in
+        ]
+    }
+
+    /// P is in milliseconds
+    fn dwell(&self, seconds: f64) -> String {
+        format!("G4 S{seconds}")
+    }
+
+    fn program_end(&self) -> Option<String> {
+        None
+    }
+}
+
+
+pub struct Smoothieware;
+
+impl PostProcessor for Smoothieware {
+    fn canned_cycles(&self) -> bool {
+        true
+    }
+
+    /// P is in milliseconds
+    fn dwell(&self, seconds: f64) -> String {
+        format!("G4 S{seconds}")
+    }
+}
+
+
+pub fn post_processor(dialect: &Dialect) -> Box<dyn PostProcessor> {
+    match dialect {
+        Dialect::LinuxCnc => Box::new(LinuxCnc),
+        Dialect::Grbl => Box::new(Grbl),
+        Dialect::Mach3 => Box::new(Mach3),
+        Dialect::Marlin => Box::new(Marlin),
+        Dialect::Smoothieware => Box::new(Smoothieware),
+    }
+}
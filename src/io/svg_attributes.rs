@@ -0,0 +1,183 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use svg::node::Attributes;
+
+
+/// Parse a list of numbers separated by commas and/or whitespace
+pub fn parse_number_list(value: &str) -> Result<Vec<f64>> {
+    value.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|n| !n.is_empty())
+        .map(|n| n.parse::<f64>().with_context(|| format!("Invalid number {n:?}")))
+        .collect()
+}
+
+/// Bring a colour into the `#rrggbb` form where possible, so colours can be compared
+pub fn normalize_color(value: &str) -> String {
+    let value = value.trim().to_ascii_lowercase();
+
+    let named = match value.as_str() {
+        "black" => Some("#000000"),
+        "white" => Some("#ffffff"),
+        "red" => Some("#ff0000"),
+        "lime" => Some("#00ff00"),
+        "green" => Some("#008000"),
+        "blue" => Some("#0000ff"),
+        "yellow" => Some("#ffff00"),
+        "cyan" | "aqua" => Some("#00ffff"),
+        "magenta" | "fuchsia" => Some("#ff00ff"),
+        "gray" | "grey" => Some("#808080"),
+        "orange" => Some("#ffa500"),
+        _ => None,
+    };
+
+    if let Some(named) = named {
+        return named.to_string();
+    }
+
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() == 3 {
+            return hex.chars().fold(String::from("#"), |mut acc, c| {
+                acc.push(c);
+                acc.push(c);
+                acc
+            });
+        }
+        return value;
+    }
+
+    if let Some(rgb) = value.strip_prefix("rgb(").and_then(|v| v.strip_suffix(')')) {
+        let channels: Option<Vec<u8>> = rgb.split(',')
+            .map(|c| c.trim().parse().ok())
+            .collect();
+
+        if let Some(&[r, g, b]) = channels.as_deref() {
+            return format!("#{r:02x}{g:02x}{b:02x}");
+        }
+    }
+
+    value
+}
+
+/// Millimetres in one CSS pixel
+pub const MM_PER_PX: f64 = 25.4 / 96.0;
+
+fn split_unit(value: &str) -> (&str, &str) {
+    let value = value.trim();
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%');
+    (number, &value[number.len()..])
+}
+
+/// Parse a length with an optional unit into user units, which unitless lengths are in.
+///
+/// `px` is the size of a CSS pixel in user units, which the other units are converted through.
+pub fn parse_length(value: &str, px: f64) -> Result<f64> {
+    let (number, unit) = split_unit(value);
+    let number: f64 = number.parse().with_context(|| format!("Invalid length {value:?}"))?;
+
+    if unit.is_empty() {
+        return Ok(number);
+    }
+
+    let px_per_unit = match unit {
+        "px" => 1.0,
+        "mm" => 1.0 / MM_PER_PX,
+        "cm" => 10.0 / MM_PER_PX,
+        "Q" => 0.25 / MM_PER_PX,
+        "in" => 96.0,
+        "pt" => 96.0 / 72.0,
+        "pc" => 16.0,
+        unit => bail!("Unsupported length unit {unit:?} in {value:?}"),
+    };
+
+    Ok(number * px_per_unit * px)
+}
+
+/// Parse an optional length attribute, see [`parse_length`]
+pub fn get_length(attrs: &Attributes, name: &str, px: f64) -> Result<Option<f64>> {
+    attrs.get(name)
+        .map(|val| parse_length(val, px).with_context(|| format!("Invalid value of '{name}'")))
+        .transpose()
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FillRule {
+    #[default]
+    NonZero,
+    EvenOdd,
+}
+
+
+/// Inheritable presentation properties of an element
+#[derive(Clone, Debug, Default)]
+pub struct SvgStyle {
+    /// In user units of the element
+    pub stroke_width: Option<f64>,
+    pub stroke: Option<String>,
+    pub fill: Option<String>,
+    pub fill_rule: Option<FillRule>,
+}
+
+impl SvgStyle {
+    fn set(&mut self, key: &str, val: &str, px: f64) -> Result<()> {
+        let val = val.trim().trim_end_matches("!important").trim();
+
+        // Leave the property unset, so it is inherited
+        if val == "inherit" {
+            return Ok(());
+        }
+
+        match key.trim() {
+            "stroke-width" => self.stroke_width = Some(parse_length(val, px)?),
+            "stroke" => self.stroke = Some(val.to_string()),
+            "fill" => self.fill = Some(val.to_string()),
+            "fill-rule" => self.fill_rule = Some(match val {
+                "nonzero" => FillRule::NonZero,
+                "evenodd" => FillRule::EvenOdd,
+                _ => bail!("Unsupported fill-rule {val:?}"),
+            }),
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    /// Properties specified on the element itself, the `style` attribute takes precedence over presentation attributes.
+    ///
+    /// Properties with unsupported values are left unset, so they are inherited. Lengths are converted with `px`, see [`parse_length`].
+    pub fn from_attributes(attrs: &Attributes, px: f64) -> Self {
+        let mut style = Self::default();
+
+        let mut set = |key: &str, val: &str| {
+            if let Err(err) = style.set(key, val, px) {
+                warn!("Ignoring {}: {val:?}, {err}", key.trim());
+            }
+        };
+
+        for key in ["stroke-width", "stroke", "fill", "fill-rule"] {
+            if let Some(val) = attrs.get(key) {
+                set(key, val);
+            }
+        }
+
+        if let Some(val) = attrs.get("style") {
+            for prop in val.split(";") {
+                if let Some((prop_key, prop_val)) = prop.split_once(":") {
+                    set(prop_key, prop_val);
+                }
+            }
+        }
+
+        style
+    }
+
+    /// Fill in the properties that are not specified from the parent
+    pub fn inherit(self, parent: &Self) -> Self {
+        Self {
+            stroke_width: self.stroke_width.or(parent.stroke_width),
+            stroke: self.stroke.or_else(|| parent.stroke.clone()),
+            fill: self.fill.or_else(|| parent.fill.clone()),
+            fill_rule: self.fill_rule.or(parent.fill_rule),
+        }
+    }
+}
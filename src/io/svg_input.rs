@@ -1,82 +1,237 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, ops::Range};
 
 use anyhow::{bail, ensure, Context, Result};
-use geo::{Coord, LineString, MultiPolygon, Polygon, RemoveRepeatedPoints};
+use geo::{AffineOps, AffineTransform, BooleanOps, Contains, Coord, LineString, MultiPolygon, Point, Polygon, RemoveRepeatedPoints, Winding};
 use log::{error, warn};
-use svg::{node::element::{path, tag}, parser::Event, Parser};
+use svg::{node::{element::{path, tag}, Attributes}, parser::Event, Parser};
 
-use crate::{fab::Hole, shape::{Circle, IntoPolygon, ThickLineString}};
+use crate::{config::{SelectConfig, SharedFabConfig}, fab::Hole, io::{svg_attributes::{get_length, normalize_color, parse_length, parse_number_list, FillRule, SvgStyle, MM_PER_PX}, svg_transform::{parse_transform, AffineTransformExt}}, shape::{Circle, CubicBezier, EllipticalArc, Flatten, IntoPolygon, QuadraticBezier, ThickLineString, EPSILON}};
+
+/// State inherited by an element from its ancestors, combined with its own attributes
+#[derive(Clone, Debug)]
+pub struct ElementContext {
+    /// Transform from the user space of the element into the document space
+    pub transform: AffineTransform,
+    pub style: SvgStyle,
+    /// Ids of the element and its ancestors
+    pub ids: Vec<String>,
+    /// Classes of the element and its ancestors
+    pub classes: Vec<String>,
+    /// Inkscape labels of the ancestor groups
+    pub layers: Vec<String>,
+}
+
+impl ElementContext {
+    pub fn new(transform: AffineTransform) -> Self {
+        Self {
+            transform,
+            style: SvgStyle::default(),
+            ids: vec![],
+            classes: vec![],
+            layers: vec![],
+        }
+    }
+
+    pub fn is_selected(&self, select: &SelectConfig) -> bool {
+        let matches = |wanted: &Vec<String>, present: &Vec<String>| {
+            wanted.is_empty() || wanted.iter().any(|w| present.contains(w))
+        };
+
+        let colors: Vec<_> = [&self.style.stroke, &self.style.fill]
+            .into_iter()
+            .flatten()
+            .map(|c| normalize_color(c))
+            .collect();
+
+        let wanted_colors: Vec<_> = select.colors.iter()
+            .map(|c| normalize_color(c))
+            .collect();
+
+        matches(&select.layers, &self.layers)
+            && matches(&select.ids, &self.ids)
+            && matches(&select.classes, &self.classes)
+            && matches(&wanted_colors, &colors)
+    }
+}
 
 pub struct SvgContext {
-    stroke_width: Vec<Option<f64>>,
+    resolution: f64,
+    /// Stroke width in millimetres for elements that don't have one
+    default_stroke_width: Option<f64>,
+    /// Size of a CSS pixel in user units, which lengths with units are converted through
+    px: f64,
+    stack: Vec<ElementContext>,
+    /// Ids of the elements being instantiated with `<use>`
+    uses: Vec<String>,
 }
 
 impl SvgContext {
-    pub fn new() -> Self {
+    pub fn new(config: &SharedFabConfig) -> Self {
         Self {
-            stroke_width: vec![],
+            resolution: config.resolution,
+            default_stroke_width: config.default_stroke_width,
+            px: 1.0,
+            stack: vec![],
+            uses: vec![],
         }
     }
 
+    /// Context of an element with `attrs` in the current group
+    pub fn element(&self, attrs: &HashMap<String, svg::node::Value>) -> Result<ElementContext> {
+        let parent = self.stack.last().cloned().unwrap_or_else(|| ElementContext::new(AffineTransform::identity()));
+
+        let transform = match attrs.get("transform") {
+            Some(val) => parse_transform(val)?.compose(&parent.transform),
+            None => parent.transform,
+        };
+
+        let style = SvgStyle::from_attributes(attrs, self.px).inherit(&parent.style);
+
+        let mut ids = parent.ids;
+        ids.extend(attrs.get("id").map(|id| id.to_string()));
+
+        let mut classes = parent.classes;
+        classes.extend(attrs.get("class").into_iter().flat_map(|c| c.split_whitespace().map(String::from)));
+
+        Ok(ElementContext {
+            transform,
+            style,
+            ids,
+            classes,
+            layers: parent.layers,
+        })
+    }
+
     pub fn group_push(&mut self, attrs: &HashMap<String, svg::node::Value>) -> Result<()> {
-        let mut stroke_width: Option<f64> = None;
-
-        for (attr, val) in attrs {
-            match attr.as_str() {
-                "style" => {
-                    for prop in val.split(";") {
-                        if let Some((prop_key, prop_val)) = prop.split_once(":") {
-                            let prop_key = prop_key.trim();
-                            let prop_val = prop_val.trim();
-
-                            match prop_key {
-                                "stroke-width" => {
-                                    stroke_width = Some(prop_val.parse()?);
-                                },
-                                _ => {}
-                            }
-                        }
-                    }
-                },
-                "transform" => {
-                    ensure!(*val == "translate(0 0) scale(1 1)", "only no-op transform is supported");
-                },
-                attr => {
-                    bail!("Attribute {attr} (value {val}) is not supported");
-                },
+        let mut group = self.element(attrs)?;
+        group.layers.extend(attrs.get("inkscape:label").map(|label| label.to_string()));
+        self.stack.push(group);
+        Ok(())
+    }
+
+    /// Set up the transform from the user space of the root `svg` element into millimetres.
+    ///
+    /// Without `width` and `height` there is no physical size, so the user units are taken to be millimetres,
+    /// and lengths with units are converted into them. Otherwise a CSS pixel is one user unit, as in SVG.
+    pub fn document_push(&mut self, attrs: &HashMap<String, svg::node::Value>) -> Result<()> {
+        ensure!(self.stack.is_empty(), "Nested svg elements are not supported");
+
+        let size = |name| -> Result<Option<f64>> {
+            match attrs.get(name) {
+                Some(val) if val.trim().ends_with('%') => Ok(None),
+                Some(val) => Ok(Some(parse_length(val, 1.0)? * MM_PER_PX)),
+                None => Ok(None),
             }
-        }
+        };
+
+        let width = size("width")?;
+        let height = size("height")?;
+
+        let view_box = match attrs.get("viewBox") {
+            Some(val) => match parse_number_list(val)?.as_slice() {
+                &[x, y, w, h] if w > 0.0 && h > 0.0 => Some((x, y, w, h)),
+                _ => bail!("Invalid viewBox {val:?}"),
+            },
+            None => None,
+        };
+
+        self.px = if width.is_none() && height.is_none() { MM_PER_PX } else { 1.0 };
+
+        let transform = match (view_box, width, height) {
+            (_, None, None) => AffineTransform::identity(),
+            (None, ..) => AffineTransform::scale(MM_PER_PX, MM_PER_PX, Coord::zero()),
+            (Some((vb_x, vb_y, vb_w, vb_h)), width, height) => {
+                // A missing dimension follows the aspect ratio of the view box
+                let width = width.unwrap_or_else(|| height.unwrap() * vb_w / vb_h);
+                let height = height.unwrap_or(width * vb_h / vb_w);
+
+                let mut sx = width / vb_w;
+                let mut sy = height / vb_h;
+
+                let par = attrs.get("preserveAspectRatio").map(|v| v.to_string()).unwrap_or_default();
+                let mut par = par.split_whitespace();
+                let align = par.next().unwrap_or("xMidYMid");
+                let slice = match par.next() {
+                    None | Some("meet") => false,
+                    Some("slice") => true,
+                    Some(other) => bail!("Invalid preserveAspectRatio mode {other:?}"),
+                };
+
+                let (align_x, align_y) = if align == "none" {
+                    (0.0, 0.0)
+                } else {
+                    let s = if slice { sx.max(sy) } else { sx.min(sy) };
+                    sx = s;
+                    sy = s;
+
+                    let factor = |a: &str| -> Result<f64> {
+                        match a {
+                            "Min" => Ok(0.0),
+                            "Mid" => Ok(0.5),
+                            "Max" => Ok(1.0),
+                            _ => bail!("Invalid preserveAspectRatio alignment {align:?}"),
+                        }
+                    };
+
+                    ensure!(align.len() == 8 && align.starts_with('x') && align[4..].starts_with('Y'), "Invalid preserveAspectRatio alignment {align:?}");
+                    (factor(&align[1..4])?, factor(&align[5..8])?)
+                };
+
+                let tx = (width - vb_w * sx) * align_x - vb_x * sx;
+                let ty = (height - vb_h * sy) * align_y - vb_y * sy;
+
+                AffineTransform::new(sx, 0.0, tx, 0.0, sy, ty)
+            },
+        };
 
-        self.stroke_width.push(stroke_width);
+        self.stack.push(ElementContext::new(transform));
 
         Ok(())
     }
 
     pub fn group_pop(&mut self) -> Result<()> {
-        assert!(self.stroke_width.pop().is_some());
+        assert!(self.stack.pop().is_some());
         Ok(())
     }
 
-    pub fn get_stroke_width(&self) -> Result<f64> {
-        let val = self.stroke_width.iter().filter_map(|x| *x).last();
-        if let Some(val) = val {
-            return Ok(val);
+    /// Stroke width of the element in millimetres
+    pub fn get_stroke_width(&self, el: &ElementContext) -> Result<f64> {
+        if let Some(val) = el.style.stroke_width {
+            return Ok(val * el.transform.scale_mean());
         }
 
-        bail!("default (not set) stroke width is not supported");
+        self.default_stroke_width.context("Stroke width is not set, consider setting default_stroke_width")
     }
 }
 
 
+/// Control point of the previous curve command, used by the smooth curve commands
+#[derive(Clone, Copy, Debug)]
+enum LastControl {
+    Cubic(Coord),
+    Quadratic(Coord),
+}
+
 #[derive(Clone, Debug)]
 pub struct PathBuilder {
+    /// The current subpath
     inner: LineString,
+    /// Finished closed subpaths
+    closed: Vec<LineString>,
+    /// Finished open subpaths
+    open: Vec<LineString>,
+    resolution: f64,
+    last_control: Option<LastControl>,
 }
 
 impl PathBuilder {
-    pub fn new() -> Self {
+    pub fn new(resolution: f64) -> Self {
         Self {
             inner: LineString::new(vec![]),
+            closed: vec![],
+            open: vec![],
+            resolution,
+            last_control: None,
         }
     }
 
@@ -84,20 +239,35 @@ impl PathBuilder {
         self.inner.0.last().copied().context("Tried to get the current line position, but the line is empty")
     }
 
-    pub fn moveto(mut self, ps: impl Iterator<Item = Coord>) -> Result<Self> {
-        ensure!(self.inner.0.len() == 0, "Move To is only supported as the first command");
-        self.inner.0.extend(ps);
-        Ok(self)
+    fn finish_subpath(&mut self) {
+        let subpath = std::mem::replace(&mut self.inner, LineString::new(vec![]));
+
+        // A lone point is left behind by a Move To or a Close Path
+        if subpath.0.len() >= 2 {
+            self.open.push(subpath);
+        }
+    }
+
+    /// Start a new subpath at the first point, the rest of the points are connected with lines
+    pub fn moveto(mut self, mut ps: impl Iterator<Item = Coord>) -> Result<Self> {
+        let p0 = ps.next().context("Move To needs at least one point")?;
+        self.finish_subpath();
+        self.inner.0.push(p0);
+        self.lineto(ps)
     }
 
     pub fn lineto(mut self, ps: impl Iterator<Item = Coord>) -> Result<Self> {
         ensure!(self.inner.0.len() > 0, "Line To can not be the first command");
         self.inner.0.extend(ps);
+        self.last_control = None;
         Ok(self)
     }
 
-    pub fn moveby(self, ps: impl Iterator<Item = Coord>) -> Result<Self> {
-        self.moveto(ps)
+    /// Same as [`Self::moveto`], but relative to the current position, if there is one
+    pub fn moveby(self, mut ps: impl Iterator<Item = Coord>) -> Result<Self> {
+        let p0 = ps.next().context("Move By needs at least one point")?;
+        let p0 = self.get_position().map(|p| p + p0).unwrap_or(p0);
+        self.moveto(std::iter::once(p0))?.lineby(ps)
     }
 
     pub fn lineby(mut self, ps: impl Iterator<Item = Coord>) -> Result<Self> {
@@ -105,22 +275,215 @@ impl PathBuilder {
         for p in ps {
             self.inner.0.push(self.get_position()? + p);
         }
+        self.last_control = None;
         Ok(self)
     }
 
-    pub fn close(mut self) -> Result<Polygon> {
-        self.inner.remove_repeated_points_mut();
-        ensure!(self.inner.0.len() >= 3, "Can only close a path with at least 3 points");
-        self.inner.close();
-        Ok(Polygon::new(self.inner, vec![]))
+    /// Each item is `[control1, control2, end]`
+    pub fn cubicto(mut self, ps: impl Iterator<Item = [Coord; 3]>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Cubic Curve To can not be the first command");
+        for [c1, c2, end] in ps {
+            let curve = CubicBezier::new(self.get_position()?, c1, c2, end);
+            self.inner.0.extend(curve.flatten(self.resolution));
+            self.last_control = Some(LastControl::Cubic(c2));
+        }
+        Ok(self)
     }
 
-    pub fn enthicken(mut self, thickness: f64) -> Result<ThickLineString> {
-        self.inner.remove_repeated_points_mut();
-        ensure!(self.inner.0.len() >= 2, "Can only enthicken a path with at least 2 points");
-        ensure!(!self.inner.is_closed(), "Didn't expect a line to be closed");
-        Ok(ThickLineString::new(self.inner, thickness))
+    /// Each item is `[control1, control2, end]`, relative to the start of the curve
+    pub fn cubicby(self, ps: impl Iterator<Item = [Coord; 3]>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Cubic Curve By can not be the first command");
+        let mut builder = self;
+        for [c1, c2, end] in ps {
+            let p = builder.get_position()?;
+            builder = builder.cubicto([[p + c1, p + c2, p + end]].into_iter())?;
+        }
+        Ok(builder)
+    }
+
+    /// Each item is `[control2, end]`, the first control point is the reflection of the previous one
+    pub fn smooth_cubicto(self, ps: impl Iterator<Item = [Coord; 2]>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Smooth Cubic Curve To can not be the first command");
+        let mut builder = self;
+        for [c2, end] in ps {
+            let p = builder.get_position()?;
+            let c1 = match builder.last_control {
+                Some(LastControl::Cubic(c)) => p * 2.0 - c,
+                _ => p,
+            };
+            builder = builder.cubicto([[c1, c2, end]].into_iter())?;
+        }
+        Ok(builder)
     }
+
+    /// Each item is `[control2, end]`, relative to the start of the curve
+    pub fn smooth_cubicby(self, ps: impl Iterator<Item = [Coord; 2]>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Smooth Cubic Curve By can not be the first command");
+        let mut builder = self;
+        for [c2, end] in ps {
+            let p = builder.get_position()?;
+            builder = builder.smooth_cubicto([[p + c2, p + end]].into_iter())?;
+        }
+        Ok(builder)
+    }
+
+    /// Each item is `[control, end]`
+    pub fn quadto(mut self, ps: impl Iterator<Item = [Coord; 2]>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Quadratic Curve To can not be the first command");
+        for [c, end] in ps {
+            let curve = QuadraticBezier::new(self.get_position()?, c, end);
+            self.inner.0.extend(curve.flatten(self.resolution));
+            self.last_control = Some(LastControl::Quadratic(c));
+        }
+        Ok(self)
+    }
+
+    /// Each item is `[control, end]`, relative to the start of the curve
+    pub fn quadby(self, ps: impl Iterator<Item = [Coord; 2]>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Quadratic Curve By can not be the first command");
+        let mut builder = self;
+        for [c, end] in ps {
+            let p = builder.get_position()?;
+            builder = builder.quadto([[p + c, p + end]].into_iter())?;
+        }
+        Ok(builder)
+    }
+
+    /// The control point of each curve is the reflection of the previous one
+    pub fn smooth_quadto(self, ps: impl Iterator<Item = Coord>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Smooth Quadratic Curve To can not be the first command");
+        let mut builder = self;
+        for end in ps {
+            let p = builder.get_position()?;
+            let c = match builder.last_control {
+                Some(LastControl::Quadratic(c)) => p * 2.0 - c,
+                _ => p,
+            };
+            builder = builder.quadto([[c, end]].into_iter())?;
+        }
+        Ok(builder)
+    }
+
+    pub fn smooth_quadby(self, ps: impl Iterator<Item = Coord>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Smooth Quadratic Curve By can not be the first command");
+        let mut builder = self;
+        for end in ps {
+            let p = builder.get_position()?;
+            builder = builder.smooth_quadto([p + end].into_iter())?;
+        }
+        Ok(builder)
+    }
+
+    /// Each item is `(radii, x_axis_rotation, large_arc, sweep, end)`
+    pub fn arcto(mut self, ps: impl Iterator<Item = (Coord, f64, bool, bool, Coord)>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Elliptical Arc To can not be the first command");
+        for (radii, rotation, large_arc, sweep, end) in ps {
+            match EllipticalArc::from_endpoints(self.get_position()?, radii, rotation, large_arc, sweep, end) {
+                Some(arc) => self.inner.0.extend(arc.flatten(self.resolution)),
+                None => self.inner.0.push(end),
+            }
+        }
+        self.last_control = None;
+        Ok(self)
+    }
+
+    /// Each item is `(radii, x_axis_rotation, large_arc, sweep, end)`, `end` is relative to the start of the arc
+    pub fn arcby(self, ps: impl Iterator<Item = (Coord, f64, bool, bool, Coord)>) -> Result<Self> {
+        ensure!(self.inner.0.len() > 0, "Elliptical Arc By can not be the first command");
+        let mut builder = self;
+        for (radii, rotation, large_arc, sweep, end) in ps {
+            let p = builder.get_position()?;
+            builder = builder.arcto([(radii, rotation, large_arc, sweep, p + end)].into_iter())?;
+        }
+        Ok(builder)
+    }
+
+    /// Close the current subpath, the next one starts at the same point unless there is a Move To
+    pub fn close(mut self) -> Result<Self> {
+        let start = *self.inner.0.first().context("Close Path can not be the first command")?;
+
+        let mut ring = std::mem::replace(&mut self.inner, LineString::new(vec![start]));
+        ring.remove_repeated_points_mut();
+        ensure!(ring.0.len() >= 3, "Can only close a path with at least 3 points");
+        ring.close();
+
+        self.closed.push(ring);
+        self.last_control = None;
+        Ok(self)
+    }
+
+    /// Returns the closed and the open subpaths, transformed into the document space
+    pub fn finish(mut self, transform: &AffineTransform) -> (Vec<LineString>, Vec<LineString>) {
+        self.finish_subpath();
+
+        let mut closed = self.closed;
+        let mut open = self.open;
+
+        for line in closed.iter_mut().chain(open.iter_mut()) {
+            line.affine_transform_mut(transform);
+        }
+
+        (closed, open)
+    }
+}
+
+
+fn enthicken(mut line: LineString, thickness: f64) -> Result<ThickLineString> {
+    line.remove_repeated_points_mut();
+    ensure!(line.0.len() >= 2, "Can only enthicken a path with at least 2 points");
+    ensure!(!line.is_closed(), "Didn't expect a line to be closed");
+    Ok(ThickLineString::new(line, thickness))
+}
+
+
+/// Combine closed rings into polygons, rings inside filled regions become holes according to the fill rule.
+///
+/// The rings are expected not to intersect each other.
+fn assemble_polygons(rings: Vec<LineString>, fill_rule: FillRule) -> MultiPolygon {
+    let outlines: Vec<_> = rings.iter()
+        .map(|ring| Polygon::new(ring.clone(), vec![]))
+        .collect();
+
+    // Indices of the rings that contain each ring
+    let ancestors: Vec<Vec<usize>> = rings.iter()
+        .enumerate()
+        .map(|(i, ring)| outlines.iter()
+            .enumerate()
+            .filter(|&(j, outline)| i != j && outline.contains(&Point::from(ring.0[0])))
+            .map(|(j, _)| j)
+            .collect())
+        .collect();
+
+    let winding = |i: usize| if rings[i].is_ccw() { 1 } else { -1 };
+
+    let is_filled = |i: usize| match fill_rule {
+        FillRule::EvenOdd => ancestors[i].len() % 2 == 0,
+        FillRule::NonZero => winding(i) + ancestors[i].iter().map(|&j| winding(j)).sum::<i32>() != 0,
+    };
+
+    let mut result = MultiPolygon::new(vec![]);
+
+    for i in 0..rings.len() {
+        if !is_filled(i) {
+            continue;
+        }
+
+        // Direct children are the rings whose closest ancestor is this one
+        let holes = (0..rings.len())
+            .filter(|&j| ancestors[j].len() == ancestors[i].len() + 1 && ancestors[j].contains(&i))
+            .map(|j| rings[j].clone())
+            .collect();
+
+        // Filled regions that touch each other along a hole are merged back together here
+        result = result.union(&Polygon::new(rings[i].clone(), holes));
+    }
+
+    result
+}
+
+
+fn params_to_coords(params: &[f32]) -> impl Iterator<Item = Coord> + '_ {
+    params.chunks_exact(2).map(|p| Coord { x: p[0] as f64, y: p[1] as f64 })
 }
 
 
@@ -170,8 +533,13 @@ impl SvgPrimitives {
         self.lines = new_lines;
     }
 
-    pub fn add_from_path(&mut self, ctx: &SvgContext, path_data: path::Data) -> Result<()> {
-        let mut builder = PathBuilder::new();
+    pub fn add_from_path(&mut self, ctx: &SvgContext, el: &ElementContext, path_data: path::Data) -> Result<()> {
+        let transform = &el.transform;
+        let scale = transform.scale_max();
+        ensure!(scale > EPSILON, "The path is transformed into nothing");
+
+        // Flatten in the user space, so the resolution needs to account for the transform
+        let mut builder = PathBuilder::new(ctx.resolution / scale);
 
         for command in path_data.iter() {
             use svg::node::element::path::{Command::*, Position::*};
@@ -210,45 +578,100 @@ impl SvgPrimitives {
                 &HorizontalLine(Relative, ref params) => {
                     builder = builder.lineby(params.iter().map(|x| Coord { x: *x as f64, y: 0.0 }))?;
                 },
+                &CubicCurve(_, ref params) => {
+                    ensure!(params.len() % 6 == 0);
+                    let mut pts = params_to_coords(params);
+                    let pts = std::iter::from_fn(|| Some([pts.next()?, pts.next()?, pts.next()?]));
+
+                    match command {
+                        CubicCurve(Absolute, ..) => builder = builder.cubicto(pts)?,
+                        CubicCurve(Relative, ..) => builder = builder.cubicby(pts)?,
+                        _ => unreachable!(),
+                    }
+                },
+                | &SmoothCubicCurve(_, ref params)
+                | &QuadraticCurve(_, ref params) => {
+                    ensure!(params.len() % 4 == 0);
+                    let mut pts = params_to_coords(params);
+                    let pts = std::iter::from_fn(|| Some([pts.next()?, pts.next()?]));
+
+                    match command {
+                        SmoothCubicCurve(Absolute, ..) => builder = builder.smooth_cubicto(pts)?,
+                        SmoothCubicCurve(Relative, ..) => builder = builder.smooth_cubicby(pts)?,
+                        QuadraticCurve(Absolute, ..) => builder = builder.quadto(pts)?,
+                        QuadraticCurve(Relative, ..) => builder = builder.quadby(pts)?,
+                        _ => unreachable!(),
+                    }
+                },
+                &SmoothQuadraticCurve(_, ref params) => {
+                    ensure!(params.len() % 2 == 0);
+                    let pts = params_to_coords(params);
+
+                    match command {
+                        SmoothQuadraticCurve(Absolute, ..) => builder = builder.smooth_quadto(pts)?,
+                        SmoothQuadraticCurve(Relative, ..) => builder = builder.smooth_quadby(pts)?,
+                        _ => unreachable!(),
+                    }
+                },
                 &EllipticalArc(_, ref params) => {
                     ensure!(params.len() % 7 == 0);
-                    warn!("Elliptical arc replaced with a straight line!");
                     let pts = params.chunks(7).filter_map(|p| {
-                        if let [_, _, _, _, _, x, y] = p {
-                            Some(Coord { x: *x as f64, y: *y as f64 })
+                        if let [rx, ry, rotation, large_arc, sweep, x, y] = p {
+                            Some((
+                                Coord { x: *rx as f64, y: *ry as f64 },
+                                *rotation as f64,
+                                *large_arc != 0.0,
+                                *sweep != 0.0,
+                                Coord { x: *x as f64, y: *y as f64 },
+                            ))
                         } else {
                             None
                         }
                     });
 
                     match command {
-                        EllipticalArc(Absolute, ..) => builder = builder.lineto(pts)?,
-                        EllipticalArc(Relative, ..) => builder = builder.lineby(pts)?,
+                        EllipticalArc(Absolute, ..) => builder = builder.arcto(pts)?,
+                        EllipticalArc(Relative, ..) => builder = builder.arcby(pts)?,
                         _ => unreachable!(),
                     }
                 },
                 &Close => {
-                    let polygon = builder.close()?;
-                    self.polygons.push(polygon);
-                    return Ok(());
-                },
-                command => {
-                    bail!("Unsupported path command {command:?}");
+                    builder = builder.close()?;
                 },
             }
         }
 
-        // TODO: handle stroke width set on the elements themselves
+        let (closed, open) = builder.finish(transform);
+
+        if !closed.is_empty() {
+            self.polygons.extend(assemble_polygons(closed, el.style.fill_rule.unwrap_or_default()));
+        }
 
-        let line = builder.enthicken(ctx.get_stroke_width()?)?;
-        self.add_line(line);
+        if !open.is_empty() {
+            let stroke_width = ctx.get_stroke_width(el)?;
+            for line in open {
+                self.add_line(enthicken(line, stroke_width)?);
+            }
+        }
 
         Ok(())
     }
 
-    pub fn add_circle(&mut self, center: Coord, radius: f64) -> Result<()> {
+    pub fn add_circle(&mut self, ctx: &SvgContext, el: &ElementContext, center: Coord, radius: f64) -> Result<()> {
         ensure!(radius > 0.0, "Circle radius should be greater than 0");
-        self.circles.push(Circle::new(center, radius));
+
+        let transform = &el.transform;
+        let scale = transform.scale_max();
+        ensure!(scale > EPSILON, "The circle is transformed into nothing");
+
+        if transform.is_similarity() {
+            self.circles.push(Circle::new(transform.apply(center), radius * scale));
+        } else {
+            // Non-uniform scaling or skewing turns the circle into an ellipse
+            let polygon = Circle::new(center, radius).into_polygon(ctx.resolution / scale);
+            self.polygons.push(polygon.affine_transform(transform));
+        }
+
         Ok(())
     }
 
@@ -271,11 +694,216 @@ impl SvgPrimitives {
 }
 
 
-pub fn process_svg(parser: Parser) -> Result<SvgPrimitives> {
-    let mut ctx = SvgContext::new();
-    let mut shapes = SvgPrimitives::new();
+fn rect_to_path(attrs: &Attributes, px: f64) -> Result<path::Data> {
+    let x = get_length(attrs, "x", px)?.unwrap_or(0.0);
+    let y = get_length(attrs, "y", px)?.unwrap_or(0.0);
+    let w = get_length(attrs, "width", px)?.context("No 'width' on rect")?;
+    let h = get_length(attrs, "height", px)?.context("No 'height' on rect")?;
+
+    ensure!(w > 0.0 && h > 0.0, "Rect should have positive width and height");
+
+    // A missing radius defaults to the other one
+    let auto = |name| -> Result<Option<f64>> {
+        match attrs.get(name) {
+            Some(val) if val.trim() == "auto" => Ok(None),
+            _ => get_length(attrs, name, px),
+        }
+    };
+
+    let (rx, ry) = match (auto("rx")?, auto("ry")?) {
+        (Some(rx), Some(ry)) => (rx, ry),
+        (Some(r), None) | (None, Some(r)) => (r, r),
+        (None, None) => (0.0, 0.0),
+    };
+
+    let rx = rx.max(0.0).min(w / 2.0);
+    let ry = ry.max(0.0).min(h / 2.0);
+
+    let data = if rx > 0.0 && ry > 0.0 {
+        path::Data::new()
+            .move_to((x + rx, y))
+            .horizontal_line_to(x + w - rx)
+            .elliptical_arc_to((rx, ry, 0, 0, 1, x + w, y + ry))
+            .vertical_line_to(y + h - ry)
+            .elliptical_arc_to((rx, ry, 0, 0, 1, x + w - rx, y + h))
+            .horizontal_line_to(x + rx)
+            .elliptical_arc_to((rx, ry, 0, 0, 1, x, y + h - ry))
+            .vertical_line_to(y + ry)
+            .elliptical_arc_to((rx, ry, 0, 0, 1, x + rx, y))
+            .close()
+    } else {
+        path::Data::new()
+            .move_to((x, y))
+            .horizontal_line_to(x + w)
+            .vertical_line_to(y + h)
+            .horizontal_line_to(x)
+            .close()
+    };
+
+    Ok(data)
+}
+
+fn ellipse_to_path(attrs: &Attributes, px: f64) -> Result<path::Data> {
+    let cx = get_length(attrs, "cx", px)?.unwrap_or(0.0);
+    let cy = get_length(attrs, "cy", px)?.unwrap_or(0.0);
+    let rx = get_length(attrs, "rx", px)?.context("No 'rx' on ellipse")?;
+    let ry = get_length(attrs, "ry", px)?.context("No 'ry' on ellipse")?;
+
+    ensure!(rx > 0.0 && ry > 0.0, "Ellipse radii should be greater than 0");
+
+    Ok(path::Data::new()
+        .move_to((cx + rx, cy))
+        .elliptical_arc_to((rx, ry, 0, 1, 1, cx - rx, cy))
+        .elliptical_arc_to((rx, ry, 0, 1, 1, cx + rx, cy))
+        .close())
+}
+
+fn line_to_path(attrs: &Attributes, px: f64) -> Result<path::Data> {
+    let x1 = get_length(attrs, "x1", px)?.unwrap_or(0.0);
+    let y1 = get_length(attrs, "y1", px)?.unwrap_or(0.0);
+    let x2 = get_length(attrs, "x2", px)?.unwrap_or(0.0);
+    let y2 = get_length(attrs, "y2", px)?.unwrap_or(0.0);
+
+    Ok(path::Data::new()
+        .move_to((x1, y1))
+        .line_to((x2, y2)))
+}
+
+fn poly_to_path(attrs: &Attributes, closed: bool) -> Result<path::Data> {
+    let points = parse_number_list(attrs.get("points").context("No 'points' on a polyline or polygon")?)?;
+    ensure!(points.len() >= 4, "Expected at least 2 points");
+
+    if points.len() % 2 != 0 {
+        warn!("Odd number of coordinates in 'points', ignoring the last one");
+    }
+
+    let mut pts = points.chunks_exact(2);
+    let p0 = pts.next().unwrap();
+
+    let mut data = path::Data::new().move_to((p0[0], p0[1]));
+    for p in pts {
+        data = data.line_to((p[0], p[1]));
+    }
+
+    if closed {
+        data = data.close();
+    }
+
+    Ok(data)
+}
+
+/// Convert a basic shape into the equivalent path, with lengths converted with `px`, see [`parse_length`]
+fn basic_shape_to_path(name: &str, attrs: &Attributes, px: f64) -> Result<path::Data> {
+    match name {
+        tag::Rectangle => rect_to_path(attrs, px),
+        tag::Ellipse => ellipse_to_path(attrs, px),
+        tag::Line => line_to_path(attrs, px),
+        tag::Polyline => poly_to_path(attrs, false),
+        tag::Polygon => poly_to_path(attrs, true),
+        name => bail!("{name} is not a basic shape"),
+    }
+}
+
+
+/// Events of a whole document, with the elements that can be referenced by `<use>`
+struct SvgDocument<'l> {
+    events: Vec<Event<'l>>,
+    /// Ranges of events making up the elements with an id
+    definitions: HashMap<String, Range<usize>>,
+}
+
+impl<'l> SvgDocument<'l> {
+    fn new(parser: Parser<'l>) -> Self {
+        let events: Vec<_> = parser.collect();
+        let mut definitions = HashMap::new();
+
+        for (i, event) in events.iter().enumerate() {
+            if let Event::Tag(_, tag::Type::Start | tag::Type::Empty, attrs) = event
+                && let Some(id) = attrs.get("id")
+            {
+                definitions.insert(id.to_string(), i..Self::find_end(&events, i) + 1);
+            }
+        }
+
+        Self {
+            events,
+            definitions,
+        }
+    }
+
+    /// Index of the event that closes the element starting at `start`
+    fn find_end(events: &[Event], start: usize) -> usize {
+        let mut depth = 0;
+
+        for (i, event) in events.iter().enumerate().skip(start) {
+            match event {
+                Event::Tag(_, tag::Type::Start, _) => depth += 1,
+                Event::Tag(_, tag::Type::End, _) => depth -= 1,
+                _ => {},
+            }
+
+            if depth == 0 {
+                return i;
+            }
+        }
+
+        events.len() - 1
+    }
+}
+
+
+/// Instantiate the element referenced by a `<use>` with `attrs`
+fn process_use(ctx: &mut SvgContext, shapes: &mut SvgPrimitives, select: &SelectConfig, doc: &SvgDocument, attrs: &Attributes) -> Result<()> {
+    let href = attrs.get("href")
+        .or_else(|| attrs.get("xlink:href"))
+        .context("No 'href' on use")?;
+
+    let Some(id) = href.trim().strip_prefix('#') else {
+        bail!("Only references within the document are supported, got {href:?}");
+    };
+
+    let range = doc.definitions.get(id).with_context(|| format!("Element {id:?} is not defined"))?.clone();
+    ensure!(!ctx.uses.iter().any(|u| u == id), "Element {id:?} references itself");
+
+    // The offset is applied after the transform of the use element
+    let x = get_length(attrs, "x", ctx.px)?.unwrap_or(0.0);
+    let y = get_length(attrs, "y", ctx.px)?.unwrap_or(0.0);
+
+    let mut attrs = attrs.clone();
+    let transform = attrs.get("transform").map(|t| t.to_string()).unwrap_or_default();
+    attrs.insert("transform".into(), format!("{transform} translate({x} {y})").into());
+
+    ctx.group_push(&attrs)?;
+    ctx.uses.push(id.to_string());
+
+    let result = match &doc.events[range.start] {
+        // Symbols are only rendered through references, as groups
+        Event::Tag(tag::Symbol, tag::Type::Start, attrs) => {
+            if attrs.contains_key("viewBox") {
+                warn!("viewBox on symbol {id:?} is ignored");
+            }
+
+            ctx.group_push(attrs)
+                .and_then(|_| process_events(ctx, shapes, select, doc, range.start + 1..range.end - 1))
+                .and_then(|_| ctx.group_pop())
+        },
+        _ => process_events(ctx, shapes, select, doc, range),
+    };
+
+    ctx.uses.pop();
+    ctx.group_pop()?;
+
+    result
+}
+
+
+fn process_events(ctx: &mut SvgContext, shapes: &mut SvgPrimitives, select: &SelectConfig, doc: &SvgDocument, range: Range<usize>) -> Result<()> {
+    let mut i = range.start;
+
+    while i < range.end {
+        let event = &doc.events[i];
+        i += 1;
 
-    for event in parser {
         match event {
 
             /* Ignore some events */
@@ -284,27 +912,76 @@ pub fn process_svg(parser: Parser) -> Result<SvgPrimitives> {
             | Event::Declaration(..)
             | Event::Text(..)
             | Event::Comment(..)
-            | Event::Tag(tag::SVG, ..)
             | Event::Tag(tag::Description, ..)
             | Event::Tag(tag::Text, ..)
             | Event::Tag(tag::Title, ..) => {},
 
+            /* Skip definitions, they are only rendered through references */
+
+            | Event::Tag(tag::Definitions, tag::Type::Start, ..)
+            | Event::Tag(tag::Symbol, tag::Type::Start, ..) => {
+                i = SvgDocument::find_end(&doc.events, i - 1) + 1;
+            },
+            | Event::Tag(tag::Definitions, tag::Type::Empty, ..)
+            | Event::Tag(tag::Symbol, tag::Type::Empty, ..) => {},
+
+            /* Handle the document viewport */
+
+            Event::Tag(tag::SVG, tag::Type::Start, attrs) => {
+                ctx.document_push(attrs)?;
+            },
+            Event::Tag(tag::SVG, tag::Type::End, ..) => {
+                ctx.group_pop()?;
+            },
+
             /* Handle group opening and closing */
 
             Event::Tag(tag::Group, tag::Type::Start, attrs) => {
-                ctx.group_push(&attrs)?;
+                ctx.group_push(attrs)?;
             },
             Event::Tag(tag::Group, tag::Type::End, ..) => {
                 ctx.group_pop()?;
             }
 
+            /* Handle references */
+
+            Event::Tag(tag::Use, ty @ (tag::Type::Empty | tag::Type::Start), attrs) => {
+                if *ty == tag::Type::Start {
+                    i = SvgDocument::find_end(&doc.events, i - 1) + 1;
+                }
+
+                if let Err(err) = process_use(ctx, shapes, select, doc, attrs) {
+                    error!("Error processing {event:?}, skipping it:\n{err}");
+                }
+            },
+
             /* Handle paths */
 
-            Event::Tag(tag::Path, tag::Type::Empty, ref attrs) => {
+            Event::Tag(tag::Path, tag::Type::Empty, attrs) => {
                 let data = attrs.get("d").context("No 'd' on a path")?;
                 let data = path::Data::parse(data)?;
+                let el = ctx.element(attrs)?;
+                if !el.is_selected(select) {
+                    continue;
+                }
 
-                if let Err(err) = shapes.add_from_path(&ctx, data) {
+                if let Err(err) = shapes.add_from_path(ctx, &el, data) {
+                    error!("Error processing {event:?}, skipping it:\n{err}");
+                }
+            },
+
+            /* Handle basic shapes other than circles */
+
+            Event::Tag(name @ (tag::Rectangle | tag::Ellipse | tag::Line | tag::Polyline | tag::Polygon), tag::Type::Empty, attrs) => {
+                let el = ctx.element(attrs)?;
+                if !el.is_selected(select) {
+                    continue;
+                }
+
+                let result = basic_shape_to_path(name, attrs, ctx.px)
+                    .and_then(|data| shapes.add_from_path(ctx, &el, data));
+
+                if let Err(err) = result {
                     error!("Error processing {event:?}, skipping it:\n{err}");
                 }
             },
@@ -312,11 +989,15 @@ pub fn process_svg(parser: Parser) -> Result<SvgPrimitives> {
             /* Handle circles */
 
             Event::Tag(tag::Circle, tag::Type::Empty, attrs) => {
-                let cx: f64 = attrs.get("cx").context("No 'cx' on circle")?.parse()?;
-                let cy: f64 = attrs.get("cy").context("No 'cy' on circle")?.parse()?;
-                let r: f64 = attrs.get("r").context("No 'r' on circle")?.parse()?;
+                let cx = get_length(attrs, "cx", ctx.px)?.context("No 'cx' on circle")?;
+                let cy = get_length(attrs, "cy", ctx.px)?.context("No 'cy' on circle")?;
+                let r = get_length(attrs, "r", ctx.px)?.context("No 'r' on circle")?;
+                let el = ctx.element(attrs)?;
+                if !el.is_selected(select) {
+                    continue;
+                }
 
-                shapes.add_circle(Coord { x: cx, y: cy }, r)?;
+                shapes.add_circle(ctx, &el, Coord { x: cx, y: cy }, r)?;
             },
 
             /* Everything else is not supported */
@@ -327,5 +1008,17 @@ pub fn process_svg(parser: Parser) -> Result<SvgPrimitives> {
         }
     }
 
+    Ok(())
+}
+
+
+pub fn process_svg(config: &SharedFabConfig, select: &SelectConfig, parser: Parser) -> Result<SvgPrimitives> {
+    let doc = SvgDocument::new(parser);
+
+    let mut ctx = SvgContext::new(config);
+    let mut shapes = SvgPrimitives::new();
+
+    process_events(&mut ctx, &mut shapes, select, &doc, 0..doc.events.len())?;
+
     Ok(shapes)
 }
@@ -1,7 +1,7 @@
-use geo::{LineString, Point};
+use geo::{Euclidean, Length, LineInterpolatePoint, LineString};
 use svg::{node::element, Document};
 
-use crate::fab::{FabContourData, FabData, FabHoleData, FabOperation, Hole};
+use crate::fab::{FabCarveData, FabContourData, FabData, FabFinish, FabHoleData, FabOperation, FabTabs, Hole};
 
 pub struct ViewBox {
     pub min_x: f64,
@@ -39,7 +39,11 @@ impl ViewBox {
     }
 }
 
-fn make_svg_path(mut points: impl Iterator<Item = Point>, view_box: &mut ViewBox) -> element::Path {
+fn make_svg_path(contour: &LineString, view_box: &mut ViewBox) -> element::Path {
+    let closed = contour.is_closed();
+
+    // The last point of a closed contour is the same as the first
+    let mut points = contour.points().skip(if closed { 1 } else { 0 });
     let p0 = points.next().unwrap();
 
     let mut data = element::path::Data::new();
@@ -51,7 +55,9 @@ fn make_svg_path(mut points: impl Iterator<Item = Point>, view_box: &mut ViewBox
         view_box.include(p.x_y());
     }
 
-    data = data.close();
+    if closed {
+        data = data.close();
+    }
 
     element::Path::new()
         .set("d", data)
@@ -65,7 +71,7 @@ fn make_svg_paths(contours: &Vec<LineString>, fill: &str, stroke: &str, view_box
         .set("stroke-width", 1);
 
     for contour in contours {
-        g_contours = g_contours.add(make_svg_path(contour.points().skip(1), view_box));
+        g_contours = g_contours.add(make_svg_path(contour, view_box));
     }
 
     g_contours
@@ -88,6 +94,54 @@ fn make_svg_holes(holes: &Vec<Hole>, fill: &str, view_box: &mut ViewBox) -> elem
     g_holes
 }
 
+fn make_svg_tabs(contours: &Vec<LineString>, tabs: &FabTabs, fill: &str) -> element::Group {
+    let mut g_tabs = element::Group::new()
+        .set("fill", fill)
+        .set("stroke", "none");
+
+    for (contour, spans) in contours.iter().zip(&tabs.spans) {
+        let length = contour.length::<Euclidean>();
+
+        for &(start, end) in spans {
+            let Some(center) = contour.line_interpolate_point((start + end) / 2.0 / length) else {
+                continue;
+            };
+
+            g_tabs = g_tabs.add(element::Circle::new()
+                .set("cx", center.x())
+                .set("cy", center.y())
+                .set("r", (end - start) / 2.0));
+        }
+    }
+
+    g_tabs
+}
+
+fn make_svg_cut(data: &FabContourData, finish: Option<&FabFinish>, view_box: &mut ViewBox) -> element::Group {
+    let mut g = make_svg_paths(&data.contours, "#4774AA22", "red", view_box);
+
+    if let Some(tabs) = &data.tabs {
+        g = g.add(make_svg_tabs(&data.contours, tabs, "#E0B000AA"));
+    }
+
+    if let Some(FabFinish { data, .. }) = finish {
+        g = g.add(make_svg_paths(&data.contours, "none", "#B0201EFF", view_box));
+    }
+
+    g
+}
+
+fn make_svg_carve(data: &FabCarveData, view_box: &mut ViewBox) -> element::Group {
+    let paths: Vec<_> = data.paths.iter().map(|(path, _)| path.clone()).collect();
+    let mut g = make_svg_paths(&paths, "none", "#6A3FA0FF", view_box);
+
+    if let Some(FabContourData { contours, .. }) = &data.clearing {
+        g = g.add(make_svg_paths(contours, "none", "#C67A1EFF", view_box));
+    }
+
+    g
+}
+
 pub fn make_svg(fds: &Vec<FabData>) -> Document {
     let mut view_box = ViewBox::new();
 
@@ -96,9 +150,13 @@ pub fn make_svg(fds: &Vec<FabData>) -> Document {
     for data in fds {
         let g = match &data.operation {
             FabOperation::Engrave(FabContourData { contours, .. }) => make_svg_paths(contours, "none", "#4774AAFF", &mut view_box),
-            FabOperation::Cut(FabContourData { contours, .. }) => make_svg_paths(contours, "#4774AA22", "red", &mut view_box),
-            FabOperation::Drilling(FabHoleData { holes, .. }) => make_svg_holes(holes, "#329544FF", &mut view_box),
+            FabOperation::Chamfer(FabContourData { contours, .. }) => make_svg_paths(contours, "none", "#3A9A8AFF", &mut view_box),
+            FabOperation::Cut { data, finish } => make_svg_cut(data, finish.as_ref(), &mut view_box),
+            FabOperation::Pocket(FabContourData { contours, .. }) => make_svg_paths(contours, "none", "#C67A1EFF", &mut view_box),
+            FabOperation::Carve(data) => make_svg_carve(data, &mut view_box),
+            FabOperation::Drilling { data: FabHoleData { holes, .. }, .. } => make_svg_holes(holes, "#329544FF", &mut view_box),
             FabOperation::Boring { data: FabHoleData { holes, .. }, .. } => make_svg_holes(holes, "#893566FF", &mut view_box),
+            FabOperation::ThreadMilling { data: FabHoleData { holes, .. }, .. } => make_svg_holes(holes, "#2E8C8CFF", &mut view_box),
         };
 
         doc = doc.add(g);
@@ -0,0 +1,114 @@
+use anyhow::{bail, ensure, Context, Result};
+use geo::{AffineTransform, Coord};
+
+use crate::{io::svg_attributes::parse_number_list, shape::EPSILON};
+
+
+pub trait AffineTransformExt {
+    /// Largest factor by which the transform stretches distances
+    fn scale_max(&self) -> f64;
+
+    /// Factor by which the transform scales areas, as a length
+    fn scale_mean(&self) -> f64;
+
+    /// Whether the transform preserves the shape of circles
+    fn is_similarity(&self) -> bool;
+}
+
+impl AffineTransformExt for AffineTransform {
+    fn scale_max(&self) -> f64 {
+        let (q, r) = singular_value_parts(self);
+        q + r
+    }
+
+    fn scale_mean(&self) -> f64 {
+        (self.a() * self.e() - self.b() * self.d()).abs().sqrt()
+    }
+
+    fn is_similarity(&self) -> bool {
+        let (q, r) = singular_value_parts(self);
+        q.min(r) < EPSILON * q.max(r).max(1.0)
+    }
+}
+
+/// The singular values of the linear part of the transform are `q + r` and `|q - r|`
+fn singular_value_parts(t: &AffineTransform) -> (f64, f64) {
+    let e = (t.a() + t.e()) / 2.0;
+    let f = (t.a() - t.e()) / 2.0;
+    let g = (t.d() + t.b()) / 2.0;
+    let h = (t.d() - t.b()) / 2.0;
+
+    (e.hypot(h), f.hypot(g))
+}
+
+
+/// Parse the value of an SVG `transform` attribute
+pub fn parse_transform(value: &str) -> Result<AffineTransform> {
+    let mut transform = AffineTransform::identity();
+
+    let value = value.trim();
+    ensure!(value.is_empty() || value.ends_with(')'), "Malformed transform {value:?}");
+
+    for item in value.split(')') {
+        let item = item.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if item.is_empty() {
+            continue;
+        }
+
+        let (name, args) = item.split_once('(').with_context(|| format!("Malformed transform {value:?}"))?;
+        let args = parse_number_list(args)?;
+
+        let t = match (name.trim(), args.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => AffineTransform::new(a, c, e, b, d, f),
+            ("translate", &[tx]) => AffineTransform::translate(tx, 0.0),
+            ("translate", &[tx, ty]) => AffineTransform::translate(tx, ty),
+            ("scale", &[s]) => AffineTransform::scale(s, s, Coord::zero()),
+            ("scale", &[sx, sy]) => AffineTransform::scale(sx, sy, Coord::zero()),
+            ("rotate", &[angle]) => AffineTransform::rotate(angle, Coord::zero()),
+            ("rotate", &[angle, cx, cy]) => AffineTransform::rotate(angle, Coord { x: cx, y: cy }),
+            ("skewX", &[angle]) => AffineTransform::skew(angle, 0.0, Coord::zero()),
+            ("skewY", &[angle]) => AffineTransform::skew(0.0, angle, Coord::zero()),
+            (name, args) => bail!("Unsupported transform {name}{args:?}"),
+        };
+
+        // The rightmost transform is applied first
+        transform = t.compose(&transform);
+    }
+
+    Ok(transform)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use geo::Vector2DOps;
+
+    use super::*;
+
+    #[test]
+    fn transform_parsing() -> Result<()> {
+        let p = Coord { x: 1.0, y: 0.0 };
+
+        let t = parse_transform("translate(10, 20) scale(2)")?;
+        assert!((t.apply(p) - Coord { x: 12.0, y: 20.0 }).magnitude() < EPSILON);
+
+        // The rightmost transform is applied first, whichever way geo composes them
+        let t = parse_transform("translate(10,0) scale(2)")?;
+        assert!((t.apply(Coord { x: 3.0, y: 4.0 }) - Coord { x: 16.0, y: 8.0 }).magnitude() < EPSILON);
+
+        let t = parse_transform("rotate(90 1 1)")?;
+        assert!((t.apply(p) - Coord { x: 2.0, y: 1.0 }).magnitude() < EPSILON);
+
+        let t = parse_transform("matrix(1 0 0 1 5 -5),skewX(45)")?;
+        assert!((t.apply(Coord { x: 0.0, y: 1.0 }) - Coord { x: 6.0, y: -4.0 }).magnitude() < EPSILON);
+
+        assert!((parse_transform("scale(2, 3)")?.scale_max() - 3.0).abs() < EPSILON);
+        assert!(!parse_transform("scale(2, 3)")?.is_similarity());
+        assert!(parse_transform("rotate(30) scale(-2, 2)")?.is_similarity());
+
+        assert!(parse_transform("translate(1 2").is_err());
+        assert!(parse_transform("perspective(1)").is_err());
+
+        Ok(())
+    }
+}
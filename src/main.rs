@@ -55,7 +55,7 @@ fn run(args: Args) -> Result<()> {
     for (i, job) in config.jobs.into_iter().enumerate() {
         let mut content = String::new();
         let parser = svg::open(&job.input, &mut content)?;
-        let primitives = process_svg(parser)?;
+        let primitives = process_svg(&config.shared, &job.select, parser)?;
 
         info!("Job {i:02} - processed the SVG");
 
@@ -0,0 +1,185 @@
+use std::f64::consts::PI;
+
+use geo::{Coord, Line, Vector2DOps};
+
+use super::EPSILON;
+
+
+/// A move along a path, from the end of the previous one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathSegment {
+    Line {
+        end: Coord,
+    },
+    Arc {
+        end: Coord,
+        center: Coord,
+        ccwise: bool,
+    },
+}
+
+
+fn circle_through(a: Coord, b: Coord, c: Coord) -> Option<Coord> {
+    let d = 2.0 * (a.x * (b.y - c.y) + b.x * (c.y - a.y) + c.x * (a.y - b.y));
+
+    if d.abs() < EPSILON {
+        return None;
+    }
+
+    let (a2, b2, c2) = (a.magnitude_squared(), b.magnitude_squared(), c.magnitude_squared());
+
+    Some(Coord {
+        x: (a2 * (b.y - c.y) + b2 * (c.y - a.y) + c2 * (a.y - b.y)) / d,
+        y: (a2 * (c.x - b.x) + b2 * (a.x - c.x) + c2 * (b.x - a.x)) / d,
+    })
+}
+
+
+/// An arc through the first, middle and last points, if all of the points and the lines between them
+/// are within `tolerance` of it, and it turns the same way all along, at most half a circle
+fn fit_arc(points: &[Coord], tolerance: f64) -> Option<PathSegment> {
+    let a = points[0];
+    let b = points[points.len() / 2];
+    let c = *points.last().unwrap();
+
+    // Nearly straight paths are left as lines
+    let chord = Line::new(a, c);
+    let straight = chord.delta().try_normalize().is_some_and(|d| points.iter()
+        .all(|&p| d.wedge_product(p - a).abs() <= tolerance));
+    if straight {
+        return None;
+    }
+
+    let center = circle_through(a, b, c)?;
+    let radius = (a - center).magnitude();
+    let ccwise = (b - a).wedge_product(c - b) > 0.0;
+
+    let mut sweep = 0.0;
+
+    for pair in points.windows(2) {
+        let (p, q) = (pair[0], pair[1]);
+        let (vp, vq) = (p - center, q - center);
+
+        // Every step goes around the center the same way
+        let turn = vp.wedge_product(vq);
+        if (turn > 0.0) != ccwise || turn.abs() < EPSILON {
+            return None;
+        }
+
+        sweep += turn.atan2(vp.dot_product(vq)).abs();
+
+        let mid = (p + q) / 2.0;
+        if ((q - center).magnitude() - radius).abs() > tolerance || radius - (mid - center).magnitude() > tolerance {
+            return None;
+        }
+    }
+
+    if sweep > PI + EPSILON {
+        return None;
+    }
+
+    Some(PathSegment::Arc {
+        end: c,
+        center,
+        ccwise,
+    })
+}
+
+
+/// Moves following the points, replacing runs of at least 3 lines with arcs where they fit within `tolerance`
+pub fn fit_arcs(points: &[Coord], tolerance: f64) -> Vec<PathSegment> {
+    let mut segments = vec![];
+
+    let mut i = 0;
+    while i + 1 < points.len() {
+        // Extend the arc as long as it still fits
+        let mut best = None;
+        for j in i + 3..points.len() {
+            match fit_arc(&points[i..=j], tolerance) {
+                Some(arc) => best = Some((j, arc)),
+                None => break,
+            }
+        }
+
+        match best {
+            Some((j, arc)) => {
+                segments.push(arc);
+                i = j;
+            },
+            None => {
+                segments.push(PathSegment::Line { end: points[i + 1] });
+                i += 1;
+            },
+        }
+    }
+
+    segments
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circle_points(center: Coord, radius: f64, from: f64, to: f64, count: usize) -> Vec<Coord> {
+        (0..=count)
+            .map(|i| from + (to - from) * i as f64 / count as f64)
+            .map(|angle| center + Coord { x: angle.cos(), y: angle.sin() } * radius)
+            .collect()
+    }
+
+    #[test]
+    fn quarter_circle() {
+        let center = Coord { x: 1.0, y: 2.0 };
+        let points = circle_points(center, 5.0, 0.0, PI / 2.0, 20);
+
+        let segments = fit_arcs(&points, 0.01);
+        assert_eq!(segments.len(), 1, "Got {segments:?}");
+
+        let PathSegment::Arc { end, center: c, ccwise } = segments[0] else {
+            panic!("Expected an arc, got {:?}", segments[0]);
+        };
+
+        assert!((end - points[20]).magnitude() < EPSILON);
+        assert!((c - center).magnitude() < 0.001);
+        assert!(ccwise);
+    }
+
+    #[test]
+    fn full_circle() {
+        let points = circle_points(Coord::zero(), 3.0, 0.0, -2.0 * PI, 64);
+
+        let segments = fit_arcs(&points, 0.01);
+        assert!(segments.len() >= 2 && segments.len() <= 3, "Got {segments:?}");
+        assert!(segments.iter().all(|s| matches!(s, PathSegment::Arc { ccwise: false, .. })));
+    }
+
+    #[test]
+    fn square_and_s_curve() {
+        let square = vec![
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 1.0, y: 0.0 },
+            Coord { x: 1.0, y: 1.0 },
+            Coord { x: 0.0, y: 1.0 },
+            Coord { x: 0.0, y: 0.0 },
+        ];
+
+        let segments = fit_arcs(&square, 0.01);
+        assert_eq!(segments.len(), 4);
+        assert!(segments.iter().all(|s| matches!(s, PathSegment::Line { .. })));
+
+        // Two arcs turning opposite ways
+        let mut points = circle_points(Coord { x: 0.0, y: 0.0 }, 1.0, PI, 0.0, 16);
+        points.extend(circle_points(Coord { x: 2.0, y: 0.0 }, 1.0, PI, 2.0 * PI, 16).into_iter().skip(1));
+
+        let segments = fit_arcs(&points, 0.01);
+        let arcs: Vec<_> = segments.iter()
+            .filter_map(|s| match s {
+                PathSegment::Arc { ccwise, .. } => Some(*ccwise),
+                PathSegment::Line { .. } => None,
+            })
+            .collect();
+
+        assert_eq!(arcs, vec![false, true], "Got {segments:?}");
+    }
+}
@@ -0,0 +1,286 @@
+use std::f64::consts::TAU;
+
+use geo::{Coord, Vector2DOps};
+
+use super::{Flatten, EPSILON};
+
+
+/// Number of segments needed to keep the chord error of a curve below `resolution`,
+/// given the upper bound of its second derivative magnitude.
+fn segments_for_deviation(second_derivative_max: f64, resolution: f64) -> usize {
+    let segments = (second_derivative_max / (8.0 * resolution)).sqrt().ceil();
+
+    if segments.is_finite() {
+        (segments as usize).max(1)
+    } else {
+        1
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct CubicBezier {
+    pub start: Coord,
+    pub control1: Coord,
+    pub control2: Coord,
+    pub end: Coord,
+}
+
+impl CubicBezier {
+    pub fn new(start: Coord, control1: Coord, control2: Coord, end: Coord) -> Self {
+        Self {
+            start,
+            control1,
+            control2,
+            end,
+        }
+    }
+
+    pub fn point_at(&self, t: f64) -> Coord {
+        let mt = 1.0 - t;
+
+        self.start * (mt * mt * mt)
+            + self.control1 * (3.0 * mt * mt * t)
+            + self.control2 * (3.0 * mt * t * t)
+            + self.end * (t * t * t)
+    }
+}
+
+impl Flatten for CubicBezier {
+    fn flatten(&self, resolution: f64) -> Vec<Coord> {
+        let d1 = self.start - self.control1 * 2.0 + self.control2;
+        let d2 = self.control1 - self.control2 * 2.0 + self.end;
+        let second_derivative_max = 6.0 * d1.magnitude().max(d2.magnitude());
+
+        let segments = segments_for_deviation(second_derivative_max, resolution);
+
+        (1..segments)
+            .map(|i| self.point_at(i as f64 / segments as f64))
+            .chain(std::iter::once(self.end))
+            .collect()
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct QuadraticBezier {
+    pub start: Coord,
+    pub control: Coord,
+    pub end: Coord,
+}
+
+impl QuadraticBezier {
+    pub fn new(start: Coord, control: Coord, end: Coord) -> Self {
+        Self {
+            start,
+            control,
+            end,
+        }
+    }
+
+    pub fn point_at(&self, t: f64) -> Coord {
+        let mt = 1.0 - t;
+
+        self.start * (mt * mt)
+            + self.control * (2.0 * mt * t)
+            + self.end * (t * t)
+    }
+}
+
+impl Flatten for QuadraticBezier {
+    fn flatten(&self, resolution: f64) -> Vec<Coord> {
+        let d = self.start - self.control * 2.0 + self.end;
+        let second_derivative_max = 2.0 * d.magnitude();
+
+        let segments = segments_for_deviation(second_derivative_max, resolution);
+
+        (1..segments)
+            .map(|i| self.point_at(i as f64 / segments as f64))
+            .chain(std::iter::once(self.end))
+            .collect()
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct EllipticalArc {
+    pub center: Coord,
+    pub radii: Coord,
+    /// Rotation of the ellipse x-axis in radians
+    pub rotation: f64,
+    /// Angle of the start point on the unrotated ellipse in radians
+    pub start_angle: f64,
+    /// Signed angular extent of the arc in radians, positive in the direction of increasing angles
+    pub sweep_angle: f64,
+}
+
+impl EllipticalArc {
+    /// Convert the SVG endpoint parameterization into the center parameterization.
+    ///
+    /// Radii that are too small to reach `end` are scaled up as required by the SVG specification.
+    /// Returns `None` when the arc degenerates into a straight line or nothing at all.
+    pub fn from_endpoints(start: Coord, radii: Coord, x_axis_rotation_deg: f64, large_arc: bool, sweep: bool, end: Coord) -> Option<Self> {
+        if (start - end).magnitude_squared() < EPSILON {
+            return None;
+        }
+
+        let mut rx = radii.x.abs();
+        let mut ry = radii.y.abs();
+
+        if rx < EPSILON || ry < EPSILON {
+            return None;
+        }
+
+        let rotation = x_axis_rotation_deg.to_radians();
+        let (sin, cos) = rotation.sin_cos();
+
+        // Step 1: compute the midpoint in the rotated frame
+        let half = (start - end) / 2.0;
+        let p = Coord {
+            x: cos * half.x + sin * half.y,
+            y: -sin * half.x + cos * half.y,
+        };
+
+        // Make sure the radii are large enough
+        let lambda = (p.x * p.x) / (rx * rx) + (p.y * p.y) / (ry * ry);
+        if lambda > 1.0 {
+            let k = lambda.sqrt();
+            rx *= k;
+            ry *= k;
+        }
+
+        // Step 2: compute the center in the rotated frame
+        let num = rx * rx * ry * ry - rx * rx * p.y * p.y - ry * ry * p.x * p.x;
+        let den = rx * rx * p.y * p.y + ry * ry * p.x * p.x;
+        let mut k = (num / den).max(0.0).sqrt();
+        if large_arc == sweep {
+            k = -k;
+        }
+
+        let c = Coord {
+            x: k * rx * p.y / ry,
+            y: -k * ry * p.x / rx,
+        };
+
+        // Step 3: compute the center in the original frame
+        let mid = (start + end) / 2.0;
+        let center = Coord {
+            x: cos * c.x - sin * c.y + mid.x,
+            y: sin * c.x + cos * c.y + mid.y,
+        };
+
+        // Step 4: compute the angles
+        let angle = |u: Coord, v: Coord| -> f64 {
+            (u.x * v.y - u.y * v.x).atan2(u.x * v.x + u.y * v.y)
+        };
+
+        let v1 = Coord { x: (p.x - c.x) / rx, y: (p.y - c.y) / ry };
+        let v2 = Coord { x: (-p.x - c.x) / rx, y: (-p.y - c.y) / ry };
+
+        let start_angle = angle(Coord { x: 1.0, y: 0.0 }, v1);
+        let mut sweep_angle = angle(v1, v2);
+
+        if !sweep && sweep_angle > 0.0 {
+            sweep_angle -= TAU;
+        } else if sweep && sweep_angle < 0.0 {
+            sweep_angle += TAU;
+        }
+
+        Some(Self {
+            center,
+            radii: Coord { x: rx, y: ry },
+            rotation,
+            start_angle,
+            sweep_angle,
+        })
+    }
+
+    pub fn point_at(&self, t: f64) -> Coord {
+        let theta = self.start_angle + self.sweep_angle * t;
+        let (sin, cos) = self.rotation.sin_cos();
+
+        let x = self.radii.x * theta.cos();
+        let y = self.radii.y * theta.sin();
+
+        self.center + Coord {
+            x: cos * x - sin * y,
+            y: sin * x + cos * y,
+        }
+    }
+}
+
+impl Flatten for EllipticalArc {
+    fn flatten(&self, resolution: f64) -> Vec<Coord> {
+        let radius = self.radii.x.max(self.radii.y);
+
+        // Largest angle step for which the sagitta stays within the resolution
+        let step = 2.0 * (1.0 - (resolution / radius).min(1.0)).acos();
+        let segments = (self.sweep_angle.abs() / step).ceil() as usize;
+        let segments = segments.max(1);
+
+        (1..=segments)
+            .map(|i| self.point_at(i as f64 / segments as f64))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use geo::{Distance, Euclidean, Line, Point};
+
+    use super::*;
+
+    #[test]
+    fn cubic_flatten_deviation() {
+        let resolution = 0.01;
+
+        let curve = CubicBezier::new(
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 0.0, y: 10.0 },
+            Coord { x: 10.0, y: 10.0 },
+            Coord { x: 10.0, y: 0.0 },
+        );
+
+        let points: Vec<_> = std::iter::once(curve.start).chain(curve.flatten(resolution)).collect();
+
+        assert!((*points.last().unwrap() - curve.end).magnitude() < EPSILON);
+
+        let segments = points.len() - 1;
+        for (i, w) in points.windows(2).enumerate() {
+            let chord = Line::new(w[0], w[1]);
+
+            for j in 1..10 {
+                let t = (i as f64 + j as f64 / 10.0) / segments as f64;
+                let p = Point::from(curve.point_at(t));
+                assert!(Euclidean::distance(&p, &chord) <= resolution, "Chord {i} deviates too much at t={t}");
+            }
+        }
+    }
+
+    #[test]
+    fn arc_from_endpoints() {
+        let start = Coord { x: 0.0, y: 0.0 };
+        let end = Coord { x: 10.0, y: 0.0 };
+
+        // Radii too small, should be scaled up to a half circle
+        let arc = EllipticalArc::from_endpoints(start, Coord { x: 1.0, y: 1.0 }, 0.0, false, true, end).unwrap();
+
+        assert!((arc.center - Coord { x: 5.0, y: 0.0 }).magnitude() < EPSILON);
+        assert!((arc.radii - Coord { x: 5.0, y: 5.0 }).magnitude() < EPSILON);
+        assert!((arc.sweep_angle - PI).abs() < EPSILON);
+
+        let points = arc.flatten(0.01);
+        assert!((*points.last().unwrap() - end).magnitude() < EPSILON);
+
+        for p in points {
+            assert!(((p - arc.center).magnitude() - 5.0).abs() < EPSILON);
+            assert!(p.y <= EPSILON);
+        }
+
+        // Large arc in the other direction
+        let arc = EllipticalArc::from_endpoints(start, Coord { x: 10.0, y: 10.0 }, 0.0, true, false, end).unwrap();
+        assert!(arc.sweep_angle < -PI);
+    }
+}
@@ -0,0 +1,134 @@
+use geo::{line_intersection::line_intersection, AffineOps, AffineTransform, BoundingRect, Coord, Intersects, Line, LineIntersection, LineString, Point, Polygon};
+
+
+/// Check that a straight move from `a` to `b` does not leave the region
+fn is_inside(region: &Polygon, edges: &[Line], a: Coord, b: Coord) -> bool {
+    let link = Line::new(a, b);
+
+    let crosses = edges.iter().any(|edge| matches!(
+        line_intersection(link, *edge),
+        Some(LineIntersection::SinglePoint { is_proper: true, .. })
+    ));
+
+    !crosses && region.intersects(&Point::from((a + b) / 2.0))
+}
+
+/// Parallel lines covering the region at `angle_deg` to the X axis, at most `stepover` apart.
+///
+/// Adjacent lines are linked into zig-zags where that can be done without leaving the region.
+pub fn hatch(region: &Polygon, angle_deg: f64, stepover: f64) -> Vec<LineString> {
+    // Work in a frame where the lines are horizontal
+    let rotate = AffineTransform::rotate(-angle_deg, Coord::zero());
+    let unrotate = AffineTransform::rotate(angle_deg, Coord::zero());
+
+    let region = region.affine_transform(&rotate);
+    let Some(bounds) = region.bounding_rect() else {
+        return vec![];
+    };
+
+    let edges: Vec<Line> = region.exterior().lines()
+        .chain(region.interiors().iter().flat_map(|ring| ring.lines()))
+        .collect();
+
+    let rows_count = (bounds.height() / stepover).ceil().max(1.0) as usize;
+    let spacing = bounds.height() / rows_count as f64;
+
+    // Segments of each row inside the region, as pairs of X coordinates
+    let rows: Vec<(f64, Vec<(f64, f64)>)> = (0..rows_count)
+        .map(|i| {
+            let y = bounds.min().y + spacing * (i as f64 + 0.5);
+
+            let mut xs: Vec<f64> = edges.iter()
+                .filter(|e| (e.start.y <= y) != (e.end.y <= y))
+                .map(|e| e.start.x + (y - e.start.y) * e.dx() / e.dy())
+                .collect();
+            xs.sort_by(f64::total_cmp);
+
+            let segments = xs.chunks_exact(2)
+                .map(|x| (x[0], x[1]))
+                .collect();
+
+            (y, segments)
+        })
+        .collect();
+
+    let mut used: Vec<Vec<bool>> = rows.iter()
+        .map(|(_, segments)| vec![false; segments.len()])
+        .collect();
+
+    let mut paths = vec![];
+
+    for row in 0..rows.len() {
+        for seg in 0..rows[row].1.len() {
+            if used[row][seg] {
+                continue;
+            }
+
+            let (y, (x0, x1)) = (rows[row].0, rows[row].1[seg]);
+            used[row][seg] = true;
+
+            let mut path = vec![Coord { x: x0, y }, Coord { x: x1, y }];
+            let mut forward = true;
+
+            for next in row + 1..rows.len() {
+                let end = *path.last().unwrap();
+                let (y, segments) = &rows[next];
+
+                // The next segment is traversed in the opposite direction
+                let candidate = segments.iter()
+                    .enumerate()
+                    .filter(|&(j, _)| !used[next][j])
+                    .map(|(j, &(x0, x1))| {
+                        let (a, b) = if forward { (x1, x0) } else { (x0, x1) };
+                        (j, Coord { x: a, y: *y }, Coord { x: b, y: *y })
+                    })
+                    .filter(|&(_, a, _)| is_inside(&region, &edges, end, a))
+                    .min_by(|(_, a1, _), (_, a2, _)| (a1.x - end.x).abs().total_cmp(&(a2.x - end.x).abs()));
+
+                let Some((j, a, b)) = candidate else {
+                    break;
+                };
+
+                used[next][j] = true;
+                path.push(a);
+                path.push(b);
+                forward = !forward;
+            }
+
+            paths.push(LineString::new(path).affine_transform(&unrotate));
+        }
+    }
+
+    paths
+}
+
+
+#[cfg(test)]
+mod tests {
+    use geo::{polygon, Euclidean, Length};
+
+    use super::*;
+
+    #[test]
+    fn hatch_around_hole() {
+        let region = polygon!(
+            exterior: [(x: 0.0, y: 0.0), (x: 10.0, y: 0.0), (x: 10.0, y: 10.0), (x: 0.0, y: 10.0)],
+            interiors: [[(x: 4.0, y: 4.0), (x: 6.0, y: 4.0), (x: 6.0, y: 6.0), (x: 4.0, y: 6.0)]],
+        );
+
+        let paths = hatch(&region, 0.0, 1.0);
+
+        // Rows above and below the hole are linked together, the ones beside it are split in two
+        assert!(paths.len() > 1 && paths.len() <= 4, "Got {} paths", paths.len());
+
+        let total: f64 = paths.iter().map(|p| p.length::<Euclidean>()).sum();
+        assert!(total > 80.0);
+
+        for path in &paths {
+            for line in path.lines() {
+                let mid = Point::from((line.start + line.end) / 2.0);
+                assert!(region.intersects(&mid), "{line:?} leaves the region");
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use geo::{Coord, Densify, Euclidean, LineString, Polygon, TriangulateSpade};
+
+
+/// Bits of the coordinate, for finding points that were computed the same way
+fn key(c: Coord) -> (u64, u64) {
+    (c.x.to_bits(), c.y.to_bits())
+}
+
+/// Endpoints of an edge in a fixed order, so that both triangles sharing it agree on its midpoint
+fn edge(a: Coord, b: Coord) -> (Coord, Coord) {
+    if key(a) < key(b) { (a, b) } else { (b, a) }
+}
+
+/// Join the segments into paths that only branch at their ends
+fn chain(segments: Vec<(Coord, Coord)>) -> Vec<LineString> {
+    let mut adjacency: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in segments.iter().enumerate() {
+        adjacency.entry(key(a)).or_default().push(i);
+        adjacency.entry(key(b)).or_default().push(i);
+    }
+
+    // Start at the tips and junctions, then pick up whatever loops are left
+    let mut starts: Vec<Coord> = segments.iter()
+        .flat_map(|&(a, b)| [a, b])
+        .filter(|&c| adjacency[&key(c)].len() != 2)
+        .collect();
+    starts.extend(segments.iter().map(|&(a, _)| a));
+
+    let mut used = vec![false; segments.len()];
+    let mut paths = vec![];
+
+    for start in starts {
+        while let Some(&first) = adjacency[&key(start)].iter().find(|&&s| !used[s]) {
+            let mut path = vec![start];
+            let mut now = start;
+            let mut s = first;
+
+            loop {
+                used[s] = true;
+
+                let (a, b) = segments[s];
+                now = if key(a) == key(now) { b } else { a };
+                path.push(now);
+
+                let next = &adjacency[&key(now)];
+                if next.len() != 2 {
+                    break;
+                }
+
+                match next.iter().find(|&&n| !used[n]) {
+                    Some(&n) => s = n,
+                    None => break,
+                }
+            }
+
+            paths.push(LineString::new(path));
+        }
+    }
+
+    paths
+}
+
+/// Approximate the medial axis of the polygon with the chordal axis of its constrained Delaunay triangulation.
+///
+/// The boundary is split into segments no longer than `spacing` first, which keeps the approximation close.
+/// Branches reach into the convex corners of the polygon.
+pub fn medial_axis(polygon: &Polygon, spacing: f64) -> Vec<LineString> {
+    let polygon = polygon.densify::<Euclidean>(spacing);
+
+    let Ok(triangles) = polygon.constrained_triangulation(Default::default()) else {
+        return vec![];
+    };
+
+    let mut shared: HashMap<_, usize> = HashMap::new();
+    for t in &triangles {
+        for (a, b) in [(t.v1(), t.v2()), (t.v2(), t.v3()), (t.v3(), t.v1())] {
+            let (a, b) = edge(a, b);
+            *shared.entry((key(a), key(b))).or_default() += 1;
+        }
+    }
+
+    let mut segments = vec![];
+
+    for t in &triangles {
+        let vertices = [t.v1(), t.v2(), t.v3()];
+        let centroid = (vertices[0] + vertices[1] + vertices[2]) / 3.0;
+
+        // Midpoints of the edges shared with other triangles, with the index of the opposite vertex
+        let internal: Vec<(Coord, usize)> = (0..3)
+            .filter_map(|i| {
+                let (a, b) = edge(vertices[i], vertices[(i + 1) % 3]);
+                (shared[&(key(a), key(b))] > 1).then(|| ((a + b) / 2.0, (i + 2) % 3))
+            })
+            .collect();
+
+        match internal.as_slice() {
+            // The whole polygon
+            [] => segments.extend(vertices.map(|v| (centroid, v))),
+            // A tip, going into the corner
+            &[(mid, apex)] => segments.push((mid, vertices[apex])),
+            // A sleeve, going through
+            &[(mid1, _), (mid2, _)] => segments.push((mid1, mid2)),
+            // A junction
+            _ => segments.extend(internal.iter().map(|&(mid, _)| (centroid, mid))),
+        }
+    }
+
+    segments.retain(|(a, b)| key(*a) != key(*b));
+
+    chain(segments)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use geo::{polygon, Contains, Distance, Point};
+
+    use super::*;
+
+    #[test]
+    fn rectangle_medial_axis() {
+        let rect = polygon![
+            (x: 0.0, y: 0.0),
+            (x: 10.0, y: 0.0),
+            (x: 10.0, y: 4.0),
+            (x: 0.0, y: 4.0),
+        ];
+
+        let paths = medial_axis(&rect, 0.1);
+        assert!(!paths.is_empty());
+
+        let points: Vec<_> = paths.iter().flat_map(|p| p.points()).collect();
+
+        for p in &points {
+            assert!(rect.contains(p) || Euclidean::distance(p, rect.exterior()) < 1e-6, "{p:?} is outside");
+        }
+
+        // The middle runs along the long axis
+        for x in [3.0, 5.0, 7.0] {
+            let closest = points.iter()
+                .map(|p| Euclidean::distance(p, &Point::new(x, 2.0)))
+                .fold(f64::INFINITY, f64::min);
+            assert!(closest < 0.2, "Nothing close to ({x}, 2), got {closest}");
+        }
+
+        // Every corner is reached
+        for corner in rect.exterior().points().take(4) {
+            assert!(points.iter().any(|p| Euclidean::distance(p, &corner) < 1e-6), "{corner:?} is not reached");
+        }
+    }
+}
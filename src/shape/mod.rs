@@ -1,13 +1,21 @@
+mod arc_fit;
 mod circle;
+mod curve;
+mod hatch;
 mod line;
+mod medial;
 
 use std::f64::consts::PI;
 
 use geo::{Coord, Line, Polygon, Vector2DOps};
 use log::debug;
 
+pub use arc_fit::*;
 pub use circle::*;
+pub use curve::*;
+pub use hatch::*;
 pub use line::*;
+pub use medial::*;
 
 pub const EPSILON: f64 = 0.000000001;
 
@@ -104,6 +112,12 @@ pub trait IntoPolygon {
 }
 
 
+pub trait Flatten {
+    /// Approximate the curve with points no further than `resolution` from it, excluding the starting point
+    fn flatten(&self, resolution: f64) -> Vec<Coord>;
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
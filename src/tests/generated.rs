@@ -1,3 +1,7 @@
+use geo::{BoundingRect, Vector2DOps};
+
+use crate::shape::EPSILON;
+
 use super::*;
 
 #[test]
@@ -137,3 +141,337 @@ fn polygon_grid() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn curves() -> Result<()> {
+    let dim = (100, 100);
+
+    let open = element::Path::new()
+        .set("d", element::path::Data::new()
+            .move_to((10, 10))
+            .cubic_curve_to((10, 40, 40, 40, 40, 10))
+            .smooth_cubic_curve_by((30, -30, 30, 0))
+            .quadratic_curve_to((85, 30, 90, 10)))
+        .set("fill", "none")
+        .set("stroke", "black");
+
+    let closed = element::Path::new()
+        .set("d", element::path::Data::new()
+            .move_to((20, 60))
+            .quadratic_curve_by((30, -20, 60, 0))
+            .smooth_quadratic_curve_by((0, 30))
+            .smooth_quadratic_curve_to((20, 90))
+            .close())
+        .set("fill", "black")
+        .set("stroke", "none");
+
+    let slot = element::Path::new()
+        .set("d", element::path::Data::new()
+            .move_to((20, 40))
+            .horizontal_line_to(60)
+            .elliptical_arc_by((5, 5, 0, 0, 1, 0, 10))
+            .horizontal_line_to(20)
+            .elliptical_arc_to((5, 5, 0, 0, 1, 20, 40))
+            .close())
+        .set("fill", "black")
+        .set("stroke", "none");
+
+    let g = element::Group::new()
+        .set("style", "stroke-width: 2")
+        .add(open)
+        .add(closed)
+        .add(slot);
+
+    let doc = make_test_svg(g, dim);
+    run("generated-curves", &doc, None)?;
+    run("generated-curves-offset", &doc, Some(2.0))?;
+
+    Ok(())
+}
+
+#[test]
+fn basic_shapes() -> Result<()> {
+    let dim = (100, 100);
+
+    let g = element::Group::new()
+        .set("style", "stroke-width: 2")
+        .add(element::Rectangle::new()
+            .set("x", 10)
+            .set("y", 10)
+            .set("width", 30)
+            .set("height", 20))
+        .add(element::Rectangle::new()
+            .set("x", 60)
+            .set("y", 10)
+            .set("width", 30)
+            .set("height", 20)
+            .set("rx", 5))
+        .add(element::Ellipse::new()
+            .set("cx", 25)
+            .set("cy", 55)
+            .set("rx", 15)
+            .set("ry", 8))
+        .add(element::Polygon::new()
+            .set("points", "60,45 90,45 75,70"))
+        .add(element::Line::new()
+            .set("x1", 10)
+            .set("y1", 80)
+            .set("x2", 40)
+            .set("y2", 90))
+        .add(element::Polyline::new()
+            .set("points", "60 80, 70 90, 80 80, 90 90"));
+
+    let doc = make_test_svg(g, dim);
+    run("generated-basic-shapes", &doc, None)?;
+    run("generated-basic-shapes-offset", &doc, Some(2.0))?;
+
+    Ok(())
+}
+
+#[test]
+fn document_units() -> Result<()> {
+    let config = SharedFabConfig {
+        resolution: 0.1,
+        safe_height: 0.0,
+        default_stroke_width: None,
+        arc_tolerance: None,
+        dialect: Dialect::default(),
+    };
+
+    let circle = make_circle(Coord { x: 50.0, y: 50.0 }, 10.0);
+
+    // 200 user units across 100 mm
+    let doc = svg::Document::new()
+        .set("width", "100mm")
+        .set("height", "50mm")
+        .set("viewBox", (0, 0, 200, 100))
+        .add(circle.clone());
+
+    let content = doc.to_string();
+    let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+    let c = &primitives.circles[0];
+
+    assert!((c.center - Coord { x: 25.0, y: 25.0 }).magnitude() < EPSILON, "Got {c:?}");
+    assert!((c.radius - 5.0).abs() < EPSILON, "Got {c:?}");
+
+    // Centered horizontally, 1 inch per 100 user units
+    let doc = svg::Document::new()
+        .set("width", "4in")
+        .set("height", "1in")
+        .set("viewBox", (0, 0, 100, 100))
+        .add(circle);
+
+    let content = doc.to_string();
+    let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+    let c = &primitives.circles[0];
+
+    assert!((c.center - Coord { x: 38.1 + 12.7, y: 12.7 }).magnitude() < EPSILON, "Got {c:?}");
+    assert!((c.radius - 2.54).abs() < EPSILON, "Got {c:?}");
+
+    // Without a size the user units are millimetres, and so are lengths in millimetres
+    let doc = svg::Document::new()
+        .set("viewBox", (0, 0, 100, 100))
+        .add(element::Path::new()
+            .set("fill", "none")
+            .set("stroke-width", "0.5mm")
+            .set("d", element::path::Data::new()
+                .move_to((10.0, 20.0))
+                .line_to((90.0, 20.0))));
+
+    let content = doc.to_string();
+    let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+    let bounds = primitives.polygons(config.resolution).bounding_rect().unwrap();
+
+    assert!((bounds.height() - 0.5).abs() < 0.01, "Got {bounds:?}");
+
+    Ok(())
+}
+
+#[test]
+fn element_styles() -> Result<()> {
+    let dim = (100, 100);
+
+    let path = |y: f64| element::Path::new()
+        .set("d", element::path::Data::new()
+            .move_to((10.0, y))
+            .line_to((90.0, y)));
+
+    let g = element::Group::new()
+        .set("style", "stroke-width: 1")
+        .add(path(20.0))
+        .add(path(40.0)
+            .set("stroke-width", "3"))
+        .add(path(60.0)
+            .set("stroke-width", "3")
+            .set("style", "fill: none; stroke-width: 6"))
+        .add(element::Group::new()
+            .set("stroke-width", "8")
+            .add(path(80.0)));
+
+    let doc = make_test_svg(g, dim);
+    run("generated-element-styles", &doc, None)?;
+
+    Ok(())
+}
+
+#[test]
+fn unsupported_styles() -> Result<()> {
+    let config = SharedFabConfig {
+        resolution: 0.1,
+        safe_height: 0.0,
+        default_stroke_width: None,
+        arc_tolerance: None,
+        dialect: Dialect::default(),
+    };
+
+    let path = |y: f64| element::Path::new()
+        .set("fill", "none")
+        .set("d", element::path::Data::new()
+            .move_to((10.0, y))
+            .line_to((90.0, y)));
+
+    // The widths fall back to the group's
+    let doc = svg::Document::new()
+        .add(element::Group::new()
+            .set("stroke-width", "2")
+            .add(path(20.0)
+                .set("style", "stroke-width: 1em; fill-rule: auto"))
+            .add(path(40.0)
+                .set("stroke-width", "50%")));
+
+    let content = doc.to_string();
+    let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+    assert_eq!(primitives.lines.len(), 2);
+
+    for polygon in primitives.polygons(config.resolution) {
+        let bounds = polygon.bounding_rect().unwrap();
+        assert!((bounds.height() - 2.0).abs() < 0.01, "Got {bounds:?}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn path_holes() -> Result<()> {
+    let config = SharedFabConfig {
+        resolution: 0.1,
+        safe_height: 0.0,
+        default_stroke_width: Some(1.0),
+        arc_tolerance: None,
+        dialect: Dialect::default(),
+    };
+
+    // Both squares are drawn in the same direction, followed by an open line
+    let data = "M 10 10 H 90 V 90 H 10 Z M 30 30 H 70 V 70 H 30 Z M 10 95 H 90";
+
+    let interiors = |fill_rule: &str| -> Result<(usize, usize, usize)> {
+        let doc = svg::Document::new()
+            .add(element::Path::new()
+                .set("d", data)
+                .set("fill-rule", fill_rule));
+
+        let content = doc.to_string();
+        let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+
+        Ok((primitives.polygons.len(), primitives.polygons[0].interiors().len(), primitives.lines.len()))
+    };
+
+    assert_eq!(interiors("evenodd")?, (1, 1, 1));
+    assert_eq!(interiors("nonzero")?, (1, 0, 1));
+
+    let doc = make_test_svg(element::Group::new()
+        .add(element::Path::new()
+            .set("d", data)
+            .set("fill-rule", "evenodd")
+            .set("stroke-width", 2)), (100, 100));
+    run("generated-path-holes", &doc, Some(2.0))?;
+
+    Ok(())
+}
+
+#[test]
+fn element_selection() -> Result<()> {
+    let config = SharedFabConfig {
+        resolution: 0.1,
+        safe_height: 0.0,
+        default_stroke_width: Some(1.0),
+        arc_tolerance: None,
+        dialect: Dialect::default(),
+    };
+
+    let doc = svg::Document::new()
+        .add(element::Group::new()
+            .set("inkscape:label", "Cut")
+            .add(make_circle(Coord { x: 10.0, y: 10.0 }, 1.0)
+                .set("id", "first"))
+            .add(make_circle(Coord { x: 20.0, y: 10.0 }, 1.0)
+                .set("fill", "#F00")
+                .set("class", "hole small")))
+        .add(element::Group::new()
+            .set("inkscape:label", "Drill")
+            .set("class", "hole")
+            .add(make_circle(Coord { x: 30.0, y: 10.0 }, 1.0)));
+
+    let content = doc.to_string();
+
+    let count = |select: SelectConfig| -> Result<usize> {
+        Ok(process_svg(&config, &select, svg::read(&content)?)?.circles.len())
+    };
+
+    assert_eq!(count(SelectConfig::default())?, 3);
+    assert_eq!(count(SelectConfig { layers: vec!["Cut".into()], ..Default::default() })?, 2);
+    assert_eq!(count(SelectConfig { ids: vec!["first".into()], ..Default::default() })?, 1);
+    assert_eq!(count(SelectConfig { classes: vec!["hole".into()], ..Default::default() })?, 2);
+    assert_eq!(count(SelectConfig { colors: vec!["red".into()], ..Default::default() })?, 1);
+    assert_eq!(count(SelectConfig { layers: vec!["Drill".into()], colors: vec!["red".into()], ..Default::default() })?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn use_references() -> Result<()> {
+    let config = SharedFabConfig {
+        resolution: 0.1,
+        safe_height: 0.0,
+        default_stroke_width: Some(1.0),
+        arc_tolerance: None,
+        dialect: Dialect::default(),
+    };
+
+    let doc = svg::Document::new()
+        .add(element::Definitions::new()
+            .add(make_circle(Coord { x: 0.0, y: 0.0 }, 2.0)
+                .set("id", "hole")))
+        .add(element::Symbol::new()
+            .set("id", "pair")
+            .add(element::Use::new()
+                .set("href", "#hole"))
+            .add(element::Use::new()
+                .set("href", "#hole")
+                .set("x", 10)))
+        .add(element::Use::new()
+            .set("xlink:href", "#pair")
+            .set("x", 20)
+            .set("y", 20))
+        .add(element::Use::new()
+            .set("href", "#pair")
+            .set("transform", "scale(2)")
+            .set("y", 30));
+
+    let content = doc.to_string();
+    let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+
+    let mut circles: Vec<_> = primitives.circles.iter()
+        .map(|c| (c.center.x, c.center.y, c.radius))
+        .collect();
+    circles.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    assert_eq!(circles, vec![
+        (0.0, 60.0, 4.0),
+        (20.0, 20.0, 2.0),
+        (20.0, 60.0, 4.0),
+        (30.0, 20.0, 2.0),
+    ]);
+
+    Ok(())
+}
@@ -1,14 +1,15 @@
 mod concept;
 mod generated;
+mod operations;
 mod stress;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{ensure, Result};
-use geo::Coord;
+use geo::{Coord, LineString, Vector2DOps};
 use svg::node::element;
 
-use crate::{config::{BitShape, JobConfig, JobKind, SharedFabConfig}, fab::FabData, io::{svg_input::process_svg, svg_output::make_svg}};
+use crate::{config::{BitShape, CornerRelief, CutSide, Dialect, EntryStrategy, FinishConfig, Handedness, JobConfig, JobKind, Lead, MillingDirection, PocketStrategy, ReliefStyle, SelectConfig, SharedFabConfig, TabsConfig, ThreadKind}, fab::{FabData, FabOperation}, io::{gcode::make_gcode, svg_input::process_svg, svg_output::make_svg}, shape::EPSILON};
 
 pub const OUTDIR: &'_ str = "tmp/test-output/";
 
@@ -31,6 +32,40 @@ pub fn init_test_logger() {
 }
 
 pub fn run(name: &str, doc: &svg::Document, offset: Option<f64>) -> Result<()> {
+    let kind = JobKind::EngraveContours {
+        depth: 0.0,
+        offset: offset.unwrap_or(0.0),
+        direction: None,
+    };
+
+    run_job(name, doc, kind, BitShape::V { angle: None })?;
+    Ok(())
+}
+
+pub fn make_job(kind: JobKind, bit_shape: BitShape) -> JobConfig {
+    JobConfig {
+        kind,
+        input: PathBuf::new(),
+        select: SelectConfig::default(),
+        bit_shape,
+        feed: 0.0,
+        plunge_feed: None,
+        entry: EntryStrategy::Plunge,
+        rpm: 0.0,
+    }
+}
+
+/// Run the job on the document, returning the G-code
+pub fn run_job(name: &str, doc: &svg::Document, kind: JobKind, bit_shape: BitShape) -> Result<String> {
+    run_job_config(name, doc, make_job(kind, bit_shape))
+}
+
+pub fn run_job_config(name: &str, doc: &svg::Document, job_config: JobConfig) -> Result<String> {
+    Ok(run_job_data(name, doc, job_config)?.1)
+}
+
+/// Run the job on the document, returning the toolpaths and the G-code
+pub fn run_job_data(name: &str, doc: &svg::Document, job_config: JobConfig) -> Result<(FabData, String)> {
     init_test_logger();
     ensure_dir(&OUTDIR)?;
 
@@ -46,30 +81,109 @@ pub fn run(name: &str, doc: &svg::Document, offset: Option<f64>) -> Result<()> {
 
     let resolution = 0.1;
 
-    let primitives = process_svg(parser)?;
-
     let fab_config = SharedFabConfig {
         resolution,
-        safe_height: 0.0,
+        safe_height: 5.0,
+        default_stroke_width: None,
+        arc_tolerance: None,
+        dialect: Dialect::default(),
     };
 
+    let primitives = process_svg(&fab_config, &SelectConfig::default(), parser)?;
+
     let job_config = JobConfig {
-        kind: JobKind::EngraveContours {
-            depth: 0.0,
-            offset: offset.unwrap_or(0.0),
-        },
         input,
-        bit_shape: BitShape::V,
-        feed: 0.0,
-        rpm: 0.0,
+        ..job_config
     };
 
     let fd = FabData::new(&fab_config, job_config, primitives)?;
 
-    let doc = make_svg(&vec![fd]);
+    let ngc = make_gcode(&fab_config, &fd);
+    std::fs::write(output.with_extension("ngc"), &ngc)?;
+
+    let fds = vec![fd];
+    let doc = make_svg(&fds);
     svg::save(output, &doc)?;
 
-    Ok(())
+    let [fd] = fds.try_into().unwrap();
+    Ok((fd, ngc))
+}
+
+/// Distance along the contour to the point, if it is on the contour
+pub fn distance_along(contour: &LineString, p: Coord) -> Option<f64> {
+    let mut distance = 0.0;
+
+    for line in contour.lines() {
+        let length = line.delta().magnitude();
+
+        if length > EPSILON {
+            let t = (p - line.start).dot_product(line.delta()) / (length * length);
+            let closest = line.start + line.delta() * t.clamp(0.0, 1.0);
+
+            if (p - closest).magnitude() < 0.0001 {
+                return Some(distance + length * t.clamp(0.0, 1.0));
+            }
+        }
+
+        distance += length;
+    }
+
+    None
+}
+
+/// Where the tool is after a move
+#[derive(Debug, Clone, Copy)]
+pub struct ToolState {
+    /// Number of the G word of the move
+    pub g: u32,
+    pub p: Coord,
+    pub z: f64,
+    pub feed: f64,
+    /// Center of arcs
+    pub center: Option<Coord>,
+}
+
+/// The state after each move in the G-code, starting from the origin
+pub fn trace_gcode(ngc: &str) -> Vec<ToolState> {
+    let mut state = ToolState {
+        g: 0,
+        p: Coord::zero(),
+        z: 0.0,
+        feed: 0.0,
+        center: None,
+    };
+
+    let mut states = vec![];
+
+    for line in ngc.lines() {
+        let words: Vec<(char, f64)> = line.split_whitespace()
+            .filter_map(|word| Some((word.chars().next()?, word[1..].parse().ok()?)))
+            .collect();
+
+        let start = state.p;
+        let mut offset = Coord::zero();
+        let mut moved = false;
+
+        for &(letter, value) in &words {
+            match letter {
+                'G' if value < 4.0 || (81.0..=83.0).contains(&value) => state.g = value as u32,
+                'X' => { state.p.x = value; moved = true },
+                'Y' => { state.p.y = value; moved = true },
+                'Z' => { state.z = value; moved = true },
+                'I' => offset.x = value,
+                'J' => offset.y = value,
+                'F' => state.feed = value,
+                _ => {},
+            }
+        }
+
+        if moved {
+            state.center = matches!(state.g, 2 | 3).then_some(start + offset);
+            states.push(state);
+        }
+    }
+
+    states
 }
 
 pub fn make_line(points: Vec<Coord>, thickness: f64) -> element::Group {
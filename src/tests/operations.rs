@@ -0,0 +1,1077 @@
+use std::f64::consts::TAU;
+
+use geo::Vector2DOps;
+
+use crate::shape::EPSILON;
+
+use super::*;
+
+fn make_washer() -> element::Path {
+    element::Path::new()
+        .set("d", element::path::Data::new()
+            .move_to((10, 10))
+            .horizontal_line_to(90)
+            .vertical_line_to(90)
+            .horizontal_line_to(10)
+            .close()
+            .move_to((40, 40))
+            .vertical_line_to(60)
+            .horizontal_line_to(60)
+            .vertical_line_to(40)
+            .close())
+        .set("fill", "black")
+        .set("fill-rule", "evenodd")
+        .set("stroke", "none")
+}
+
+fn make_washer_svg() -> svg::Document {
+    make_test_svg(element::Group::new().add(make_washer()), (100, 100))
+}
+
+/// Signed distance from the point to the edges of the washer, positive on the washer and negative off it
+fn washer_distance(p: Coord) -> f64 {
+    // Positive inside the square
+    let square = |min: f64, max: f64| {
+        let inside = (p.x - min).min(max - p.x).min(p.y - min).min(max - p.y);
+        let outside = |v: f64| (min - v).max(v - max).max(0.0);
+
+        if inside > 0.0 { inside } else { -outside(p.x).hypot(outside(p.y)) }
+    };
+
+    square(10.0, 90.0).min(-square(40.0, 60.0))
+}
+
+/// Whether a bit of radius 1.5 centered at the point is clear of the washer, on the washer or off it,
+/// allowing for the flats of arcs
+fn in_waste(inside: bool, p: Coord) -> bool {
+    let clearance = 1.5 - 0.1;
+
+    if inside {
+        washer_distance(p) > clearance
+    } else {
+        washer_distance(p) < -clearance
+    }
+}
+
+/// Points along the straight moves below the surface
+fn cutting_points(ngc: &str) -> Vec<Coord> {
+    trace_gcode(ngc).windows(2)
+        .filter(|pair| pair[1].g == 1 && pair[0].z < 0.0 && pair[1].z < 0.0)
+        .flat_map(|pair| (0..=10).map(move |i| pair[0].p + (pair[1].p - pair[0].p) * (i as f64 / 10.0)))
+        .collect()
+}
+
+/// Distance from the point to the line between `a` and `b`
+fn segment_distance(p: Coord, a: Coord, b: Coord) -> f64 {
+    let d = b - a;
+    let t = if d.magnitude_squared() > EPSILON { ((p - a).dot_product(d) / d.magnitude_squared()).clamp(0.0, 1.0) } else { 0.0 };
+    (p - (a + d * t)).magnitude()
+}
+
+/// The options of `JobKind::CutContours`, so that each case only spells out the ones it changes
+struct CutKind {
+    depth: f64,
+    depth_per_pass: f64,
+    side: CutSide,
+    tabs: Option<TabsConfig>,
+    lead: Option<Lead>,
+    direction: Option<MillingDirection>,
+    finish: Option<FinishConfig>,
+    relief: Option<CornerRelief>,
+}
+
+/// Through the washer in three passes, without any of the options
+fn cut_kind(side: CutSide) -> CutKind {
+    CutKind {
+        depth: 3.0,
+        depth_per_pass: 1.0,
+        side,
+        tabs: None,
+        lead: None,
+        direction: None,
+        finish: None,
+        relief: None,
+    }
+}
+
+impl From<CutKind> for JobKind {
+    fn from(kind: CutKind) -> Self {
+        let CutKind { depth, depth_per_pass, side, tabs, lead, direction, finish, relief } = kind;
+        JobKind::CutContours { depth, depth_per_pass, side, tabs, lead, direction, finish, relief }
+    }
+}
+
+#[test]
+fn pocket_concentric() -> Result<()> {
+    let doc = make_washer_svg();
+
+    let kind = |depth_per_pass| JobKind::PocketContours {
+        depth: 3.0,
+        depth_per_pass,
+        stepover: 2.0,
+        strategy: PocketStrategy::Concentric,
+        direction: None,
+    };
+
+    let ngc = run_job("operations-pocket-concentric", &doc, kind(1.0), BitShape::Square { radius: 1.5 })?;
+
+    // On the washer and around its hole, the offsets are simplified by a fifth of the resolution
+    let points = cutting_points(&ngc);
+    assert!(!points.is_empty());
+
+    for p in points {
+        assert!(washer_distance(p) >= 1.5 - 0.03, "{p:?} cuts into the edges of the pocket");
+    }
+
+    assert!(run_job("operations-pocket-no-depth-per-pass", &doc, kind(0.0), BitShape::Square { radius: 1.5 }).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn pocket_concentric_middle() -> Result<()> {
+    // The middle is closer than a stepover to the first ring, but further than the bit radius
+    let square = make_polygon(vec![
+        Coord { x: 45.6, y: 45.6 },
+        Coord { x: 54.4, y: 45.6 },
+        Coord { x: 54.4, y: 54.4 },
+        Coord { x: 45.6, y: 54.4 },
+    ]);
+    let doc = make_test_svg(element::Group::new().add(square), (100, 100));
+
+    let kind = JobKind::PocketContours {
+        depth: 3.0,
+        depth_per_pass: 3.0,
+        stepover: 3.0,
+        strategy: PocketStrategy::Concentric,
+        direction: None,
+    };
+
+    let ngc = run_job("operations-pocket-concentric-middle", &doc, kind, BitShape::Square { radius: 1.5 })?;
+    let moves = trace_gcode(&ngc);
+
+    // Nothing is left standing on the floor of the pocket, wherever the bit fits
+    let segments: Vec<_> = moves.windows(2)
+        .filter(|pair| pair[1].g == 1 && pair[0].z == -3.0 && pair[1].z == -3.0)
+        .map(|pair| (pair[0].p, pair[1].p))
+        .collect();
+
+    for i in 0..=29 {
+        for j in 0..=29 {
+            let p = Coord { x: 47.1 + 0.2 * i as f64, y: 47.1 + 0.2 * j as f64 };
+            let distance = segments.iter()
+                .map(|&(a, b)| segment_distance(p, a, b))
+                .fold(f64::INFINITY, f64::min);
+
+            assert!(distance <= 1.5 + 0.05, "{p:?} is {distance} away from the cuts");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn pocket_raster() -> Result<()> {
+    let doc = make_washer_svg();
+
+    for finish_contour in [false, true] {
+        let kind = JobKind::PocketContours {
+            depth: 3.0,
+            depth_per_pass: 1.0,
+            stepover: 2.0,
+            strategy: PocketStrategy::Raster {
+                angle: 30.0,
+                finish_contour,
+            },
+            direction: None,
+        };
+
+        let name = format!("operations-pocket-raster{}", if finish_contour { "-finish" } else { "" });
+        let job = make_job(kind, BitShape::Square { radius: 1.5 });
+        let (fd, ngc) = run_job_data(&name, &doc, job)?;
+
+        // The lines and the links between them stay on the washer and off its hole
+        let points = cutting_points(&ngc);
+        assert!(!points.is_empty());
+
+        for p in points {
+            assert!(washer_distance(p) >= 1.5 - 0.03, "{p:?} cuts into the edges of the pocket");
+        }
+
+        // Around the outside and the hole, the bit radius away from them
+        let FabOperation::Pocket(data) = &fd.operation else {
+            panic!("Expected a pocket, got {:?}", fd.operation);
+        };
+
+        let boundaries: Vec<_> = data.contours.iter().filter(|c| c.is_closed()).collect();
+        assert_eq!(boundaries.len(), if finish_contour { 2 } else { 0 }, "Got {boundaries:?}");
+
+        for p in boundaries.iter().flat_map(|c| c.coords()) {
+            assert!((washer_distance(*p) - 1.5).abs() < 0.03, "{p:?} is off the boundary");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cut_sides() -> Result<()> {
+    let doc = make_washer_svg();
+
+    // Off the washer, on it, or along its edges
+    for (name, side, distance) in [("outside", CutSide::Outside, -1.5), ("inside", CutSide::Inside, 1.5), ("on", CutSide::On, 0.0)] {
+        let ngc = run_job(&format!("operations-cut-{name}"), &doc, cut_kind(side).into(), BitShape::Square { radius: 1.5 })?;
+
+        let points = cutting_points(&ngc);
+
+        // Both around the outside and around the hole
+        let around_hole = |p: &Coord| (p.x - 50.0).abs().max((p.y - 50.0).abs()) < 25.0;
+        assert!(points.iter().any(around_hole) && !points.iter().all(around_hole), "Got {ngc}");
+
+        for p in points {
+            assert!((washer_distance(p) - distance).abs() < 0.01, "{name}: {p:?} should be {distance} from the edges of the washer");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cut_tabs() -> Result<()> {
+    let doc = make_washer_svg();
+
+    let cases = [
+        ("count", Some(4), None, EntryStrategy::Plunge),
+        ("spacing", None, Some(30.0), EntryStrategy::Plunge),
+        ("ramp", Some(4), None, EntryStrategy::Ramp { angle: 10.0 }),
+    ];
+
+    for (name, count, spacing, entry) in cases {
+        let kind = CutKind {
+            tabs: Some(TabsConfig {
+                count,
+                spacing,
+                width: 4.0,
+                height: 1.5,
+            }),
+            ..cut_kind(CutSide::Outside)
+        };
+
+        let ramp = matches!(entry, EntryStrategy::Ramp { .. });
+        let job = JobConfig {
+            entry,
+            ..make_job(kind.into(), BitShape::Square { radius: 1.5 })
+        };
+
+        let (fd, ngc) = run_job_data(&format!("operations-cut-tabs-{name}"), &doc, job)?;
+        let FabOperation::Cut { data, .. } = &fd.operation else {
+            panic!("Expected a cut, got {:?}", fd.operation);
+        };
+        let tabs = data.tabs.as_ref().unwrap();
+        assert!((tabs.depth - 1.5).abs() < EPSILON);
+
+        let moves = trace_gcode(&ngc);
+
+        // Only ever at the top of the tabs between their ends, crossing them in the last pass
+        let mut crossings = 0;
+        for pair in moves.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+
+            for (contour, spans) in data.contours.iter().zip(&tabs.spans) {
+                for i in 1..10 {
+                    let p = from.p + (to.p - from.p) * (i as f64 / 10.0);
+                    let Some(distance) = distance_along(contour, p) else {
+                        continue;
+                    };
+
+                    if spans.iter().any(|&(start, end)| start + EPSILON < distance && distance < end - EPSILON) {
+                        assert!(from.z >= -tabs.depth - EPSILON && to.z >= -tabs.depth - EPSILON, "{from:?} to {to:?} goes through a tab");
+                        crossings += (to.z == -tabs.depth && from.z == -tabs.depth) as usize;
+                    }
+                }
+            }
+        }
+        assert!(crossings > 0);
+
+        // Going back down after the tabs along the contour, instead of plunging
+        let plunges = moves.windows(2)
+            .filter(|pair| pair[1].g == 1 && pair[1].p == pair[0].p && pair[1].z == -3.0 && pair[0].z > -3.0)
+            .count();
+
+        let spans: usize = tabs.spans.iter().map(Vec::len).sum();
+        if ramp {
+            assert_eq!(plunges, 0, "Got {ngc}");
+        } else {
+            assert_eq!(plunges, data.contours.len() + spans, "Got {ngc}");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cut_tabs_arcs() -> Result<()> {
+    let doc = make_test_svg(element::Group::new()
+        .add(make_circle(Coord { x: 50.0, y: 50.0 }, 20.0)), (100, 100));
+    let content = doc.to_string();
+
+    let config = SharedFabConfig {
+        resolution: 0.1,
+        safe_height: 5.0,
+        default_stroke_width: None,
+        arc_tolerance: Some(0.1),
+        dialect: Dialect::default(),
+    };
+
+    let kind = CutKind {
+        tabs: Some(TabsConfig {
+            count: Some(4),
+            spacing: None,
+            width: 4.0,
+            height: 1.5,
+        }),
+        ..cut_kind(CutSide::Outside)
+    };
+
+    let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+    let fd = FabData::new(&config, make_job(kind.into(), BitShape::Square { radius: 1.5 }), primitives)?;
+    let ngc = make_gcode(&config, &fd);
+
+    // Both beside and over the tabs the circle is cut with arcs around its center
+    let moves = trace_gcode(&ngc);
+    let arcs = |z: f64| moves.iter()
+        .filter(|m| m.center.is_some() && m.z == z)
+        .collect::<Vec<_>>();
+
+    for z in [-3.0, -1.5] {
+        let arcs = arcs(z);
+        assert!(!arcs.is_empty(), "No arcs at {z} in {ngc}");
+
+        for arc in arcs {
+            let center = arc.center.unwrap();
+            assert!((center - Coord { x: 50.0, y: 50.0 }).magnitude() < 0.1, "{arc:?}");
+            assert!(((arc.p - center).magnitude() - 21.5).abs() < 0.1, "{arc:?}");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cut_entries() -> Result<()> {
+    let doc = make_washer_svg();
+
+    // The helix doesn't fit between the sides of the washer and ramps instead
+    let entries = [
+        ("ramp", true, EntryStrategy::Ramp { angle: 5.0 }, true),
+        ("helix", true, EntryStrategy::Helix { radius: 1.0, angle: 10.0 }, true),
+        ("helix-outside", false, EntryStrategy::Helix { radius: 1.0, angle: 10.0 }, true),
+        ("helix-wide", true, EntryStrategy::Helix { radius: 15.0, angle: 10.0 }, false),
+    ];
+
+    for (name, inside, entry, fits) in entries {
+        let kind = cut_kind(if inside { CutSide::Inside } else { CutSide::Outside });
+
+        let (helix, angle) = match entry {
+            EntryStrategy::Helix { angle, .. } => (fits, angle),
+            EntryStrategy::Ramp { angle } => (false, angle),
+            EntryStrategy::Plunge => (false, 90.0),
+        };
+
+        let job = JobConfig {
+            feed: 600.0,
+            plunge_feed: Some(100.0),
+            entry,
+            ..make_job(kind.into(), BitShape::Square { radius: 1.5 })
+        };
+
+        let ngc = run_job_config(&format!("operations-cut-entry-{name}"), &doc, job)?;
+        let moves = trace_gcode(&ngc);
+
+        let mut helices = 0;
+        for (i, pair) in moves.windows(2).enumerate() {
+            let (from, to) = (pair[0], pair[1]);
+
+            // Around whole circles on the waste side, going on the way the contour does
+            if let Some(center) = to.center && to.z < from.z {
+                helices += 1;
+
+                let radius = (from.p - center).magnitude();
+                for i in 0..32 {
+                    let (sin, cos) = (TAU * i as f64 / 32.0).sin_cos();
+                    let p = center + Coord { x: cos, y: sin } * radius;
+                    assert!(in_waste(inside, p), "{name}: {p:?} on the helix around {center:?} cuts into the washer");
+                }
+
+                let next = moves[i + 2];
+                let r = to.p - center;
+                let tangent = if to.g == 3 { Coord { x: -r.y, y: r.x } } else { Coord { x: r.y, y: -r.x } };
+                assert!(tangent.dot_product(next.p - to.p) > 0.0, "{name}: the helix ending at {:?} goes against the contour", to.p);
+            }
+
+            // No steeper than the ramps
+            let run = (to.p - from.p).magnitude();
+            if to.g == 1 && to.z < from.z && run > EPSILON {
+                let slope = (from.z - to.z) / run;
+                assert!(slope <= angle.to_radians().tan() + EPSILON, "{name}: {from:?} to {to:?} is too steep");
+            }
+        }
+
+        assert_eq!(helices > 0, helix, "{name}: Got {ngc}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cut_leads() -> Result<()> {
+    let doc = make_washer_svg();
+
+    let leads = [
+        ("outside-arc", CutSide::Outside, Lead::Arc { radius: 3.0 }),
+        ("outside-line", CutSide::Outside, Lead::Line { length: 3.0 }),
+        ("inside-arc", CutSide::Inside, Lead::Arc { radius: 3.0 }),
+    ];
+
+    for (name, side, lead) in leads {
+        let inside = matches!(side, CutSide::Inside);
+        let arc = matches!(lead, Lead::Arc { .. });
+
+        let kind = CutKind {
+            tabs: Some(TabsConfig {
+                count: Some(2),
+                spacing: None,
+                width: 4.0,
+                height: 1.0,
+            }),
+            lead: Some(lead),
+            ..cut_kind(side)
+        };
+
+        let job = make_job(kind.into(), BitShape::Square { radius: 1.5 });
+        let (fd, ngc) = run_job_data(&format!("operations-cut-lead-{name}"), &doc, job)?;
+
+        // Going down and back up in the waste
+        for m in trace_gcode(&ngc).iter().filter(|m| m.g == 1 && (m.z == 0.0 || m.z == 5.0)) {
+            assert!(in_waste(inside, m.p), "{name}: {m:?} is on the washer");
+        }
+
+        let FabOperation::Cut { data, .. } = &fd.operation else {
+            panic!("Expected a cut, got {:?}", fd.operation);
+        };
+
+        let distance = if inside { 1.5 } else { -1.5 };
+
+        for contour in &data.contours {
+            let c = &contour.0;
+
+            // The leads are before and after the points of the offset contour
+            let on_contour = |p: &Coord| (washer_distance(*p) - distance).abs() < 0.000001;
+            let start = c.iter().position(on_contour).unwrap();
+            let end = c.iter().rposition(on_contour).unwrap();
+            assert!(start > 0 && end < c.len() - 1, "{name}: No leads on {contour:?}");
+
+            for &p in c[..start].iter().chain(&c[end + 1..]) {
+                assert!(in_waste(inside, p), "{name}: The lead through {p:?} gouges the washer");
+            }
+
+            // Arcs join the contour going the same way, lines at right angles to it
+            let direction = |a: usize, b: usize| (c[b] - c[a]).try_normalize().unwrap();
+            let lead_in = direction(start - 1, start).dot_product(direction(start, start + 1));
+            let lead_out = direction(end - 1, end).dot_product(direction(end, end + 1));
+
+            for dot in [lead_in, lead_out] {
+                if arc {
+                    assert!(dot > 0.99, "{name}: The leads of {contour:?} aren't tangent to it");
+                } else {
+                    assert!(dot.abs() < 0.0001, "{name}: The leads of {contour:?} aren't perpendicular to it");
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the closed contour goes counterclockwise
+fn is_ccwise(contour: &LineString) -> bool {
+    contour.lines().map(|line| line.start.wedge_product(line.end)).sum::<f64>() > 0.0
+}
+
+/// Check that the contours around the outside of the washer go the given way and the ones around its hole the other way
+fn assert_windings(contours: &[LineString], exterior_ccwise: bool) {
+    let around_hole = |c: &LineString| (c.0[0].x - 50.0).abs().max((c.0[0].y - 50.0).abs()) < 25.0;
+    assert!(contours.iter().any(around_hole) && !contours.iter().all(around_hole), "Got {contours:?}");
+
+    for contour in contours {
+        assert_eq!(is_ccwise(contour), exterior_ccwise != around_hole(contour), "{contour:?} goes the wrong way");
+    }
+}
+
+#[test]
+fn milling_directions() -> Result<()> {
+    let doc = make_washer_svg();
+
+    let direction = |climb| if climb { MillingDirection::Climb } else { MillingDirection::Conventional };
+
+    // With a clockwise spindle the bit climbs when the waste is on its left
+    for climb in [true, false] {
+        let name = if climb { "climb" } else { "conventional" };
+
+        for (side_name, side, inside) in [("outside", CutSide::Outside, false), ("inside", CutSide::Inside, true)] {
+            let kind = CutKind {
+                direction: Some(direction(climb)),
+                ..cut_kind(side)
+            };
+
+            let job = make_job(kind.into(), BitShape::Square { radius: 1.5 });
+            let (fd, _) = run_job_data(&format!("operations-cut-{side_name}-{name}"), &doc, job)?;
+            let FabOperation::Cut { data, .. } = &fd.operation else {
+                panic!("Expected a cut, got {:?}", fd.operation);
+            };
+
+            assert_windings(&data.contours, climb == inside);
+        }
+
+        // The waste is inside the contours of pockets
+        let kind = JobKind::PocketContours {
+            depth: 3.0,
+            depth_per_pass: 1.0,
+            stepover: 2.0,
+            strategy: PocketStrategy::Concentric,
+            direction: Some(direction(climb)),
+        };
+
+        let job = make_job(kind, BitShape::Square { radius: 1.5 });
+        let (fd, _) = run_job_data(&format!("operations-pocket-{name}"), &doc, job)?;
+        let FabOperation::Pocket(data) = &fd.operation else {
+            panic!("Expected a pocket, got {:?}", fd.operation);
+        };
+
+        assert_windings(&data.contours, climb);
+
+        // And outside of the contours that are engraved
+        let kind = JobKind::EngraveContours {
+            depth: 1.0,
+            offset: 0.0,
+            direction: Some(direction(climb)),
+        };
+
+        let job = make_job(kind, BitShape::V { angle: None });
+        let (fd, _) = run_job_data(&format!("operations-engrave-{name}"), &doc, job)?;
+        let FabOperation::Engrave(data) = &fd.operation else {
+            panic!("Expected an engraving, got {:?}", fd.operation);
+        };
+
+        assert_windings(&data.contours, !climb);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn cut_finish() -> Result<()> {
+    let doc = make_washer_svg();
+
+    for (name, side, reverse) in [("outside", CutSide::Outside, false), ("inside-reverse", CutSide::Inside, true)] {
+        let inside = matches!(side, CutSide::Inside);
+
+        let kind = CutKind {
+            lead: Some(Lead::Arc { radius: 2.0 }),
+            direction: Some(MillingDirection::Climb),
+            finish: Some(FinishConfig {
+                allowance: 0.3,
+                feed: 200.0,
+                reverse,
+            }),
+            ..cut_kind(side)
+        };
+
+        let job = JobConfig {
+            feed: 600.0,
+            ..make_job(kind.into(), BitShape::Square { radius: 1.5 })
+        };
+
+        let (fd, ngc) = run_job_data(&format!("operations-cut-finish-{name}"), &doc, job)?;
+
+        // How far into the waste the center of the bit is
+        let into_waste = |p: Coord| if inside { washer_distance(p) } else { -washer_distance(p) };
+
+        let cutting: Vec<_> = trace_gcode(&ngc).windows(2)
+            .filter(|pair| pair[1].g == 1 && pair[0].z < 0.0 && pair[1].z < 0.0 && pair[0].p != pair[1].p)
+            .map(|pair| pair[1])
+            .collect();
+
+        // The roughing passes leave the allowance, then the finishing pass goes all around at full depth
+        let finish = cutting.iter().position(|m| m.feed == 200.0).unwrap();
+        let (roughing, finishing) = cutting.split_at(finish);
+
+        for m in roughing {
+            assert!(into_waste(m.p) > 1.8 - 0.000001 && m.feed == 600.0, "{name}: Roughing at {m:?}");
+        }
+
+        for m in finishing {
+            assert!(into_waste(m.p) > 1.5 - 0.000001 && m.feed == 200.0 && m.z == -3.0, "{name}: Finishing at {m:?}");
+        }
+
+        assert!(roughing.iter().any(|m| (into_waste(m.p) - 1.8).abs() < 0.000001), "Got {ngc}");
+        assert!(finishing.iter().any(|m| (into_waste(m.p) - 1.5).abs() < 0.000001), "Got {ngc}");
+
+        // Climbing, unless the finishing pass is reversed
+        let FabOperation::Cut { data, finish: Some(finish) } = &fd.operation else {
+            panic!("Expected a cut with a finishing pass, got {:?}", fd.operation);
+        };
+
+        assert_windings(&data.contours, inside);
+        assert_windings(&finish.data.contours, inside != reverse);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn drill_pecking() -> Result<()> {
+    let dim = (100, 100);
+    let g = element::Group::new()
+        .add(make_circle(Coord { x: 20.0, y: 20.0 }, 1.0))
+        .add(make_circle(Coord { x: 80.0, y: 20.0 }, 1.0))
+        .add(make_circle(Coord { x: 50.0, y: 80.0 }, 1.0));
+
+    let doc = make_test_svg(g, dim);
+
+    let cycles = [
+        ("expanded", Some(1.5), Some(0.5), false),
+        ("g81", None, None, true),
+        ("g82", None, Some(0.5), true),
+        ("g83", Some(1.5), None, true),
+    ];
+
+    for (name, peck_depth, dwell, canned_cycle) in cycles {
+        let kind = JobKind::DrillCircles {
+            depth: 5.0,
+            radius_min: None,
+            radius_max: None,
+            peck_depth,
+            dwell,
+            canned_cycle,
+        };
+
+        let ngc = run_job(&format!("operations-drill-{name}"), &doc, kind, BitShape::Square { radius: 1.0 })?;
+        let count = |prefix: &str| ngc.lines().filter(|l| l.starts_with(prefix)).count();
+
+        match name {
+            "expanded" => {
+                // Pecks to 1.5, 3 and 4.5, all the way out after each, then down to 5
+                let feeds: Vec<_> = ngc.lines().filter(|l| l.starts_with("G1 Z-")).collect();
+                assert_eq!(feeds.len(), 4 * 3, "Got {ngc}");
+                assert_eq!(count("G0 Z5"), 1 + 3 * 3, "Got {ngc}");
+                assert_eq!(count("G4 P0.5"), 3);
+
+                // Rapids back down stop short of where the previous peck ended
+                let moves = trace_gcode(&ngc);
+                for pair in moves.windows(2) {
+                    if pair[0].g == 0 && pair[0].z < 0.0 {
+                        assert!((pair[1].z - pair[0].z + 1.5 + 0.25).abs() < EPSILON || (pair[1].z + 5.0).abs() < EPSILON, "Got {pair:?}");
+                        assert!(pair[0].z > pair[1].z + 0.25 - EPSILON);
+                    }
+                }
+            },
+            "g81" => assert_eq!(count("G98 G81"), 3),
+            "g82" => assert_eq!(count("G98 G82"), 3),
+            "g83" => assert_eq!(ngc.lines().filter(|l| l.starts_with("G98 G83") && l.contains(" Q1.5")).count(), 3),
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn bore_wide() -> Result<()> {
+    let dim = (100, 100);
+    let g = element::Group::new()
+        .add(make_circle(Coord { x: 25.0, y: 50.0 }, 4.0))
+        .add(make_circle(Coord { x: 70.0, y: 50.0 }, 10.0));
+
+    let doc = make_test_svg(g, dim);
+
+    let kind = |depth| JobKind::BoreCircles {
+        depth,
+        depth_per_turn: 1.0,
+        radius_min: None,
+        radius_max: None,
+        stepover: None,
+    };
+
+    let ngc = run_job("operations-bore-wide", &doc, kind(3.0), BitShape::Square { radius: 3.0 })?;
+
+    // The narrow hole in one helix, the wide one widened by the stepover up to the final helix
+    let mut radii: Vec<f64> = vec![];
+    for m in trace_gcode(&ngc) {
+        if let Some(center) = m.center {
+            let r = (m.p - center).magnitude();
+            if !radii.iter().any(|&other| (other - r).abs() < EPSILON) {
+                radii.push(r);
+            }
+        }
+    }
+    radii.sort_by(f64::total_cmp);
+
+    assert_eq!(radii.len(), 4, "Got {radii:?}");
+    for (r, expected) in radii.iter().zip([1.0, 3.0, 6.0, 7.0]) {
+        assert!((r - expected).abs() < 0.0001, "Got {radii:?}");
+    }
+
+    // Shallower than a turn
+    let ngc = run_job("operations-bore-shallow", &doc, kind(0.5), BitShape::Square { radius: 3.0 })?;
+    assert!(ngc.contains(" P1") && !ngc.contains(" P0"), "Got {ngc}");
+
+    Ok(())
+}
+
+#[test]
+fn thread_milling() -> Result<()> {
+    let dim = (100, 100);
+    let g = element::Group::new()
+        .add(make_circle(Coord { x: 25.0, y: 50.0 }, 3.0))
+        .add(make_circle(Coord { x: 75.0, y: 50.0 }, 8.0));
+
+    let doc = make_test_svg(g, dim);
+
+    let threads = [
+        ("internal-right", ThreadKind::Internal, Handedness::Right, Some(4.0), 1.0, 3),
+        ("external-left", ThreadKind::External, Handedness::Left, None, 1.5, 3),
+    ];
+
+    for (name, thread, hand, radius_max, pitch, g) in threads {
+        let kind = JobKind::ThreadMillCircles {
+            depth: 5.0,
+            pitch,
+            thread_depth: 0.6,
+            thread,
+            hand,
+            radius_min: None,
+            radius_max,
+        };
+
+        let ngc = run_job(&format!("operations-thread-{name}"), &doc, kind, BitShape::ThreadMill { radius: 2.0 })?;
+        let moves = trace_gcode(&ngc);
+
+        let helices: Vec<_> = moves.iter().filter(|m| m.center.is_some()).collect();
+        assert!(!helices.is_empty() && helices.iter().all(|m| m.g == g), "Got {ngc}");
+
+        // Down to the depth and no further, a pitch at a time except for the last turn
+        let deepest = moves.iter().map(|m| m.z).fold(0.0, f64::min);
+        assert!((deepest + 5.0).abs() < EPSILON, "Got {deepest}");
+
+        for pair in helices.windows(2).filter(|pair| pair[0].center == pair[1].center) {
+            let rise = (pair[1].z - pair[0].z).abs();
+            assert!(rise <= pitch + EPSILON, "Got {pair:?}");
+        }
+    }
+
+    let kind = JobKind::ThreadMillCircles {
+        depth: 5.0,
+        pitch: 1.0,
+        thread_depth: 3.5,
+        thread: ThreadKind::External,
+        hand: Handedness::Right,
+        radius_min: None,
+        radius_max: Some(4.0),
+    };
+
+    assert!(run_job("operations-thread-thin-boss", &doc, kind, BitShape::ThreadMill { radius: 4.0 }).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn v_carve() -> Result<()> {
+    let dim = (100, 100);
+    let g = element::Group::new()
+        .add(make_washer())
+        .add(make_polygon(vec![
+            Coord { x: 10.0, y: 92.0 },
+            Coord { x: 90.0, y: 92.0 },
+            Coord { x: 50.0, y: 98.0 },
+        ])
+            .set("fill", "black"));
+
+    let doc = make_test_svg(g, dim);
+
+    for (name, max_depth, clearing) in [("sharp", None, false), ("flat", Some(3.0), true)] {
+        let kind = JobKind::VCarveContours {
+            max_depth,
+            clearing,
+            depth_per_pass: clearing.then_some(1.0),
+            scallop_height: Some(0.3),
+        };
+
+        let job = JobConfig {
+            feed: 600.0,
+            plunge_feed: Some(200.0),
+            ..make_job(kind, BitShape::V { angle: Some(60.0) })
+        };
+
+        let ngc = run_job_config(&format!("operations-v-carve-{name}"), &doc, job)?;
+        let moves = trace_gcode(&ngc);
+
+        // Going deeper at the plunge feed, along the medial axis too
+        for pair in moves.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+
+            if to.g == 1 && to.z < from.z - EPSILON && to.z < 0.0 {
+                assert_eq!(to.feed, 200.0, "Going down from {from:?} to {to:?}");
+            }
+        }
+
+        let deepest = moves.iter().map(|m| -m.z).fold(0.0, f64::max);
+        assert!(max_depth.is_none_or(|max| deepest <= max + EPSILON), "Got {deepest}");
+
+        let Some(max_depth) = max_depth else {
+            continue;
+        };
+
+        // Cleared in several passes
+        let depths: Vec<_> = moves.iter().filter(|m| m.z < 0.0).map(|m| m.z).collect();
+        for depth in [-1.0, -2.0] {
+            assert!(depths.contains(&depth), "No pass at {depth}, got {ngc}");
+        }
+
+        // How deep the cone of the bit going along the moves cuts at the point
+        let half_angle_tan = 30.0f64.to_radians().tan();
+        let floor = |p: Coord| moves.windows(2)
+            .filter(|pair| pair[1].g == 1 && pair[0].z < 0.0 && pair[1].z < 0.0)
+            .map(|pair| -pair[0].z.max(pair[1].z) - segment_distance(p, pair[0].p, pair[1].p) / half_angle_tan)
+            .fold(0.0, f64::max);
+
+        // Across the washer where it is wider than the bit at the maximum depth, the ridges are no taller than the scallop height,
+        // allowing for the offsets being simplified
+        let samples = (0..=152).map(|i| 12.0 + 0.5 * i as f64)
+            .flat_map(|v| [Coord { x: v, y: 25.0 }, Coord { x: 25.0, y: v }])
+            .filter(|&p| washer_distance(p) >= max_depth * half_angle_tan);
+
+        for p in samples {
+            let depth = floor(p);
+            assert!(depth >= max_depth - 0.3 - 0.05 && depth <= max_depth + EPSILON, "The floor at {p:?} is at {depth}");
+        }
+    }
+
+    // Passes further apart than the width of the bit at the bottom would leave strips of the floor
+    let kind = JobKind::VCarveContours {
+        max_depth: Some(3.0),
+        clearing: true,
+        depth_per_pass: Some(1.0),
+        scallop_height: Some(4.0),
+    };
+    assert!(run_job("operations-v-carve-wide-scallops", &doc, kind, BitShape::V { angle: Some(60.0) }).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn v_bit_angle() -> Result<()> {
+    // The angle is optional, for the operations that don't need it
+    let shape = |yaml: &str| -> Result<BitShape> { Ok(serde_norway::from_str(yaml)?) };
+    assert_eq!(shape("V")?, BitShape::V { angle: None });
+    assert_eq!(shape("V: {}")?, BitShape::V { angle: None });
+    assert_eq!(shape("V: { angle: 60 }")?, BitShape::V { angle: Some(60.0) });
+    assert_eq!(shape("Square: { radius: 1.5 }")?, BitShape::Square { radius: 1.5 });
+    assert!(shape("Square").is_err());
+
+    let doc = make_washer_svg();
+
+    let chamfer = JobKind::ChamferContours {
+        width: 1.0,
+        clearance: 0.0,
+        side: CutSide::Outside,
+        direction: None,
+    };
+
+    assert!(run_job("operations-chamfer-no-angle", &doc, chamfer, BitShape::V { angle: None }).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn chamfer() -> Result<()> {
+    let doc = make_washer_svg();
+
+    let kind = |side, clearance| JobKind::ChamferContours {
+        width: 1.0,
+        clearance,
+        side,
+        direction: Some(MillingDirection::Climb),
+    };
+
+    // The tip of the bit is the clearance away from the edges, into the waste
+    for (name, side, distance) in [("outside", CutSide::Outside, -0.5), ("inside", CutSide::Inside, 0.5), ("on", CutSide::On, 0.0)] {
+        let clearance = distance.abs();
+        let ngc = run_job(&format!("operations-chamfer-{name}"), &doc, kind(side, clearance), BitShape::V { angle: Some(90.0) })?;
+
+        // Deep enough for the flank to reach the width of the bevel
+        let depth = (1.0 + clearance) / 45.0f64.to_radians().tan();
+        let moves: Vec<_> = trace_gcode(&ngc).into_iter().filter(|m| m.z < 0.0).collect();
+        assert!(!moves.is_empty() && moves.iter().all(|m| (m.z + depth).abs() < 0.000001), "{name}: Got {ngc}");
+
+        for p in cutting_points(&ngc) {
+            assert!((washer_distance(p) - distance).abs() < 0.01, "{name}: {p:?} should be {distance} from the edges of the washer");
+        }
+    }
+
+    assert!(run_job("operations-chamfer-on-clearance", &doc, kind(CutSide::On, 0.5), BitShape::V { angle: Some(90.0) }).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn corner_relief() -> Result<()> {
+    let dim = (100, 100);
+
+    // A part with a notch
+    let g = element::Group::new()
+        .add(make_polygon(vec![
+            Coord { x: 10.0, y: 10.0 },
+            Coord { x: 40.0, y: 10.0 },
+            Coord { x: 40.0, y: 30.0 },
+            Coord { x: 60.0, y: 30.0 },
+            Coord { x: 60.0, y: 10.0 },
+            Coord { x: 90.0, y: 10.0 },
+            Coord { x: 90.0, y: 90.0 },
+            Coord { x: 10.0, y: 90.0 },
+        ])
+            .set("fill", "black"));
+
+    let doc = make_test_svg(g, dim);
+
+    // The corners the bit can't reach from the waste, the others are left alone
+    let notch = [Coord { x: 40.0, y: 30.0 }, Coord { x: 60.0, y: 30.0 }];
+    let inside = [(10.0, 10.0), (40.0, 10.0), (60.0, 10.0), (90.0, 10.0), (90.0, 90.0), (10.0, 90.0)].map(Coord::from);
+
+    let cases = [
+        ("dogbone", CutSide::Outside, ReliefStyle::Dogbone, &notch[..]),
+        ("tbone", CutSide::Outside, ReliefStyle::TBone, &notch[..]),
+        ("tbone-inside", CutSide::Inside, ReliefStyle::TBone, &inside[..]),
+    ];
+
+    for (name, side, style, corners) in cases {
+        let dogbone = matches!(style, ReliefStyle::Dogbone);
+
+        let kind = CutKind {
+            depth_per_pass: 3.0,
+            relief: Some(CornerRelief {
+                style,
+                max_angle: 90.0,
+            }),
+            ..cut_kind(side)
+        };
+
+        let ngc = run_job(&format!("operations-relief-{name}"), &doc, kind.into(), BitShape::Square { radius: 2.0 })?;
+        let moves: Vec<_> = trace_gcode(&ngc).into_iter().filter(|m| m.z < 0.0).collect();
+
+        // Out of the corner of the path and back, once for each of the corners
+        let overcuts: Vec<_> = moves.windows(3)
+            .filter(|w| w[2].p == w[0].p && w[1].p != w[0].p)
+            .map(|w| (w[0].p, w[1].p))
+            .collect();
+        assert_eq!(overcuts.len(), corners.len(), "{name}: Got {overcuts:?}");
+
+        for &corner in corners {
+            let distance = |overcut: &&(Coord, Coord)| (overcut.1 - corner).magnitude();
+            let &(from, to) = overcuts.iter().min_by(|a, b| distance(a).total_cmp(&distance(b))).unwrap();
+
+            // The edge of the bit reaches the corner
+            assert!(((to - corner).magnitude() - 2.0).abs() < 0.000001, "{name}: The overcut to {to:?} doesn't reach {corner:?}");
+
+            // Diagonally into it, or along one of the walls
+            let v = to - from;
+            assert!(v.dot_product(corner - from) > 0.0, "{name}: The overcut to {to:?} goes away from {corner:?}");
+
+            if dogbone {
+                assert!((v.x.abs() - v.y.abs()).abs() < 0.000001, "{name}: The overcut to {to:?} isn't diagonal");
+            } else {
+                assert!(v.x.abs().min(v.y.abs()) < 0.000001, "{name}: The overcut to {to:?} isn't along a wall");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn post_processors() -> Result<()> {
+    let doc = make_test_svg(element::Group::new()
+        .add(make_circle(Coord { x: 30.0, y: 50.0 }, 5.0)), (100, 100));
+    let content = doc.to_string();
+
+    let generate = |dialect: Dialect, kind: JobKind| -> Result<String> {
+        let config = SharedFabConfig {
+            resolution: 0.1,
+            safe_height: 5.0,
+            default_stroke_width: None,
+            arc_tolerance: None,
+            dialect,
+        };
+
+        let primitives = process_svg(&config, &SelectConfig::default(), svg::read(&content)?)?;
+        let fd = FabData::new(&config, make_job(kind, BitShape::Square { radius: 1.0 }), primitives)?;
+
+        Ok(make_gcode(&config, &fd))
+    };
+
+    let bore = || JobKind::BoreCircles {
+        depth: 6.0,
+        depth_per_turn: 2.0,
+        radius_min: None,
+        radius_max: None,
+        stepover: None,
+    };
+
+    let drill = |canned_cycle| JobKind::DrillCircles {
+        depth: 5.0,
+        radius_min: None,
+        radius_max: None,
+        peck_depth: None,
+        dwell: Some(0.5),
+        canned_cycle,
+    };
+
+    let arcs = |ngc: &str| ngc.lines()
+        .filter(|l| l.starts_with("G2 ") || l.starts_with("G3 "))
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+
+    // Helices in one move, the drilling cycles are kept
+    let ngc = generate(Dialect::LinuxCnc, bore())?;
+    assert!(arcs(&ngc)[0].contains(" Z-6 ") && arcs(&ngc)[0].contains(" P3"), "Got {ngc}");
+    assert!(generate(Dialect::LinuxCnc, drill(true))?.contains("G82"));
+
+    // One move per turn, and separate moves instead of the cycles
+    let ngc = generate(Dialect::Grbl, bore())?;
+    let helix = arcs(&ngc);
+    assert!(helix.iter().all(|l| !l.contains(" P")), "Got {ngc}");
+    assert!(helix[0].contains(" Z-2 ") && helix[1].contains(" Z-4 ") && helix[2].contains(" Z-6 "), "Got {ngc}");
+
+    let ngc = generate(Dialect::Grbl, JobKind::BoreCircles {
+        depth: 1.0,
+        depth_per_turn: 2.0,
+        radius_min: None,
+        radius_max: None,
+        stepover: None,
+    })?;
+    assert!(arcs(&ngc).iter().any(|l| l.contains(" Z-1 ")), "Got {ngc}");
+
+    let ngc = generate(Dialect::Grbl, drill(true))?;
+    assert!(!ngc.contains("G8") && ngc.contains("G4 P0.5") && ngc.ends_with("M2"), "Got {ngc}");
+
+    let ngc = generate(Dialect::Mach3, drill(true))?;
+    assert!(ngc.contains("G82") && ngc.contains("\r\n") && ngc.ends_with("M30"), "Got {ngc}");
+
+    let ngc = generate(Dialect::Marlin, drill(false))?;
+    assert!(!ngc.contains("G17") && !ngc.contains("M2") && ngc.contains("G4 S0.5") && ngc.contains("M3 S0"), "Got {ngc}");
+
+    let ngc = generate(Dialect::Smoothieware, drill(false))?;
+    assert!(ngc.contains("G4 S0.5") && ngc.ends_with("M2"), "Got {ngc}");
+
+    Ok(())
+}